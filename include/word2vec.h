@@ -0,0 +1,103 @@
+#ifndef WORD2VEC_H
+#define WORD2VEC_H
+
+/* generated by cbindgen from src/ffi.rs, do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// success
+#define W2V_OK 0
+
+// failure, described by the error message
+#define W2V_ERROR -1
+
+// the word has no vector
+#define W2V_OUT_OF_VOCABULARY -2
+
+// the word is skipped by the out of vocabulary policy of the model, nothing
+// is written
+#define W2V_SKIPPED 1
+
+// A trained or loaded model.
+typedef struct W2vModel W2vModel;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Trains with the `n` command line arguments `args`, the program name
+// first, and saves the model to the output they name. Returns 0, or -1 with
+// the reason in `ptr`.
+int32_t ffi_train(const char *const *args, int32_t n, char **ptr);
+
+// Trains with the `n` command line arguments `args`, the program name
+// first, into a new model in `model`, without saving it.
+int32_t w2v_train(const char *const *args, int32_t n, struct W2vModel **model, char **error);
+
+// Reads a model saved by w2v_save or the command line into `model`.
+int32_t w2v_load(const char *path, struct W2vModel **model, char **error);
+
+// Writes the vocabulary to `path`, the vectors to `path`.vec and the
+// tokenizer to `path`.tokenizer.
+int32_t w2v_save(const struct W2vModel *model, const char *path, char **error);
+
+// Frees a model of w2v_train or w2v_load, null is ignored.
+void w2v_free(struct W2vModel *model);
+
+// Frees a string returned by the API, null is ignored.
+void w2v_string_free(char *s);
+
+// The size of the vectors, 0 for a null model.
+size_t w2v_dim(const struct W2vModel *model);
+
+// The number of words, 0 for a null model.
+size_t w2v_vocab_size(const struct W2vModel *model);
+
+// The index of `word`, below w2v_vocab_size, or -1 when it is not in the
+// vocabulary.
+int64_t w2v_index(const struct W2vModel *model, const char *word);
+
+// Writes the word at `index` to `word`, a string freed with
+// w2v_string_free. Iterates the vocabulary with indices below
+// w2v_vocab_size.
+int32_t w2v_word(const struct W2vModel *model, size_t index, char **word, char **error);
+
+// Copies the vector of `word` to `vector`, which holds `len` floats, at
+// least w2v_dim. Words out of the vocabulary follow the policy of the
+// model: W2V_OUT_OF_VOCABULARY, W2V_SKIPPED, zeros or the unknown word
+// vector.
+int32_t w2v_vector(const struct W2vModel *model,
+                   const char *word,
+                   float *vector,
+                   size_t len,
+                   char **error);
+
+// Writes the cosine similarity of `a` and `b` to `similarity`, W2V_SKIPPED
+// when the model skips one of them.
+int32_t w2v_similarity(const struct W2vModel *model,
+                       const char *a,
+                       const char *b,
+                       float *similarity,
+                       char **error);
+
+// Writes the `k` words closest to `word` by cosine similarity, the closest
+// first, to `words` and their similarities to `scores`, both arrays of `k`
+// items, and how many there are to `count`. Each word is freed with
+// w2v_string_free.
+int32_t w2v_most_similar(const struct W2vModel *model,
+                         const char *word,
+                         size_t k,
+                         char **words,
+                         float *scores,
+                         size_t *count,
+                         char **error);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* WORD2VEC_H */
@@ -0,0 +1,223 @@
+use crate::corpus::InputFormat;
+use crate::utils::{Argument, ArgumentError};
+use crate::W2vError;
+use std::fs::{self, File};
+use std::io::Write;
+use toml::{Table, Value};
+
+// a value of the config file, as it is given on the command line
+enum Setting {
+    Flag(bool),
+    Value(String),
+}
+
+fn config_error(path: &str, reason: String) -> ArgumentError {
+    ArgumentError::Config(format!("{}: {}", path, reason))
+}
+
+// the options of a toml file, or a json one when the name ends in .json, keyed
+// by their names on the command line, like `dim = 300` for --dim 300
+fn read_config(path: &str) -> Result<Vec<(String, Setting)>, ArgumentError> {
+    let text = fs::read_to_string(path).map_err(|e| config_error(path, e.to_string()))?;
+    let mut settings = Vec::new();
+    if path.ends_with(".json") {
+        let json: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(&text).map_err(|e| config_error(path, e.to_string()))?;
+        for (key, value) in json {
+            let setting = match value {
+                // the default
+                serde_json::Value::Null => continue,
+                serde_json::Value::Bool(b) => Setting::Flag(b),
+                serde_json::Value::Number(n) => Setting::Value(n.to_string()),
+                serde_json::Value::String(s) => Setting::Value(s),
+                _ => return Err(config_error(path, format!("{} is not a single value", key))),
+            };
+            settings.push((key, setting));
+        }
+    } else {
+        let table: Table = text
+            .parse()
+            .map_err(|e: toml::de::Error| config_error(path, e.to_string()))?;
+        for (key, value) in table {
+            let setting = match value {
+                Value::Boolean(b) => Setting::Flag(b),
+                Value::Integer(i) => Setting::Value(i.to_string()),
+                Value::Float(f) => Setting::Value(f.to_string()),
+                Value::String(s) => Setting::Value(s),
+                _ => return Err(config_error(path, format!("{} is not a single value", key))),
+            };
+            settings.push((key, setting));
+        }
+    }
+    Ok(settings)
+}
+
+// command line arguments for the options of the config file but those
+// given on the command line, which wins, and those the subcommand does not
+// know. clap checks them like the others
+pub fn config_args(
+    path: &str,
+    given: &dyn Fn(&str) -> bool,
+    known: &dyn Fn(&str) -> bool,
+) -> Result<Vec<String>, ArgumentError> {
+    let (mut options, mut input, mut output) = (Vec::new(), None, None);
+    for (key, setting) in read_config(path)? {
+        if given(&key) || !known(&key) {
+            continue;
+        }
+        match (key.as_str(), setting) {
+            ("config", _) => {
+                return Err(config_error(path, "config files do not nest".to_string()))
+            }
+            ("input", Setting::Value(v)) => input = Some(v),
+            ("output", Setting::Value(v)) => output = Some(v),
+            (_, Setting::Flag(true)) => options.push(format!("--{}", key)),
+            (_, Setting::Flag(false)) => {}
+            (_, Setting::Value(v)) => options.push(format!("--{}={}", key, v)),
+        }
+    }
+    // the positional arguments come in order, after those on the command line
+    if output.is_some() && input.is_none() && !given("input") {
+        return Err(config_error(
+            path,
+            "output is given without input".to_string(),
+        ));
+    }
+    options.extend(input);
+    options.extend(output);
+    Ok(options)
+}
+
+fn float(x: f32) -> Value {
+    // through the shortest decimal of the f32, not its exact f64 value
+    Value::Float(x.to_string().parse().unwrap_or(x as f64))
+}
+
+fn integer<T: TryInto<i64> + ToString + Copy>(x: T) -> Value {
+    // toml integers are i64, larger seeds are written as strings
+    x.try_into()
+        .map(Value::Integer)
+        .unwrap_or_else(|_| Value::String(x.to_string()))
+}
+
+impl Argument {
+    // every option of the train subcommand with its value, in a form
+    // `--config` reads back
+    pub fn config(&self) -> Table {
+        let mut table = Table::new();
+        let mut set = |key: &str, value: Value| {
+            table.insert(key.to_string(), value);
+        };
+        let string = |s: &str| Value::String(s.to_string());
+        set("input", string(&self.input));
+        set("output", string(&self.output));
+        set("format", string(self.format.name()));
+        match self.format {
+            InputFormat::Jsonl { ref field } => set("field", string(field)),
+            InputFormat::Csv(ref csv) => {
+                set("column", string(&csv.text.name()));
+                if let Some(ref tag) = csv.tag {
+                    set("tag_column", string(&tag.name()));
+                }
+                if let Some(ref weight) = csv.weight {
+                    set("weight_column", string(&weight.name()));
+                }
+                set("no_header", Value::Boolean(!csv.header));
+            }
+            _ => {}
+        }
+        set("lr", float(self.lr));
+        set("dim", integer(self.dim));
+        set("win", integer(self.win));
+        set("epoch", integer(self.epoch));
+        set("neg", integer(self.neg));
+        set("thread", integer(self.nthreads));
+        set("min_count", integer(self.min_count));
+        set("threshold", float(self.threshold));
+        set("lr_update", integer(self.lr_update));
+        set("lr_schedule", string(self.lr_schedule.name()));
+        set("min_lr", float(self.min_lr));
+        set("warmup", float(self.warmup));
+        set("lr_decay", float(self.lr_decay));
+        set("neg_pow", float(self.neg_pow));
+        set("deterministic", Value::Boolean(self.deterministic));
+        set("verbose", Value::Boolean(self.verbose));
+        set("lowercase", Value::Boolean(self.tokenizer.lowercase));
+        set("nfkc", Value::Boolean(self.tokenizer.nfkc));
+        set("punctuation", string(self.tokenizer.punctuation.name()));
+        set(
+            "unicode_words",
+            Value::Boolean(self.tokenizer.unicode_words),
+        );
+        set("normalize_numbers", Value::Boolean(self.tokenizer.numbers));
+        set("oov", string(self.oov.name()));
+        // options without a default are left out when not given
+        let optional = [
+            ("max_rows", self.max_rows.map(integer)),
+            ("max_vocab_size", self.max_vocab_size.map(integer)),
+            ("max_final_vocab", self.max_final_vocab.map(integer)),
+            ("seed", self.seed.map(integer)),
+            ("patience", self.patience.map(integer)),
+            ("valid_metric", self.valid_metric.map(|m| string(m.name()))),
+        ];
+        let files = [
+            ("corpus_cache", &self.corpus_cache),
+            ("read_vocab", &self.read_vocab),
+            ("noise_weights", &self.noise_weights),
+            ("metrics", &self.metrics),
+            ("valid", &self.valid),
+            ("valid_similarity", &self.valid_similarity),
+            ("valid_analogy", &self.valid_analogy),
+        ];
+        for (key, value) in optional {
+            if let Some(value) = value {
+                set(key, value);
+            }
+        }
+        for (key, file) in files {
+            if let Some(ref file) = *file {
+                set(key, string(file));
+            }
+        }
+        table
+    }
+
+    pub fn save_config(&self, path: &str) -> Result<(), W2vError> {
+        let mut file = File::create(path)?;
+        write!(file, "{}", self.config())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::parse_arguments;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn saved_config_round_trip() {
+        let output = std::env::temp_dir().join(format!("config-{}", std::process::id()));
+        let output = output.to_str().unwrap();
+        let trained = parse_arguments(&args(&format!(
+            "word2vec train corpus.csv {} --format csv --column body --tag_column id \
+             --weight_column weight --no_header --dim 30 --lr 0.01 --thread 3 --min_count 2 \
+             --lowercase --punctuation split --oov unk --max_vocab_size 1000 \
+             --seed 9223372036854775809 --deterministic",
+            output
+        )))
+        .unwrap();
+        let path = format!("{}.config.toml", output);
+        trained.save_config(&path).unwrap();
+        let loaded = parse_arguments(&args(&format!("word2vec train --config {}", path)));
+        let given = parse_arguments(&args(&format!("word2vec train --config {} --dim 7", path)));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap().config(), trained.config());
+        // the command line wins over the file
+        let given = given.unwrap();
+        assert_eq!(given.dim, 7);
+        assert_eq!(given.seed, Some(1 << 63 | 1));
+    }
+}
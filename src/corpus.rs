@@ -0,0 +1,471 @@
+use crate::file_utils::file_split_indices;
+use crate::W2vError;
+use csv::{ByteRecord, ReaderBuilder, StringRecord};
+use serde_json::Value;
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputFormat {
+    Text,
+    Parquet,
+    // one json object per line, text taken from the field at a json pointer
+    Jsonl { field: String },
+    Csv(CsvFormat),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Column {
+    Name(String),
+    Index(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvFormat {
+    pub delimiter: u8,
+    pub header: bool,
+    pub text: Column,
+    // column whose value is trained as a document vector predicting the words
+    pub tag: Option<Column>,
+    // column scaling the learning rate of the record
+    pub weight: Option<Column>,
+}
+
+// one unit of training text, a line for line based formats
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub text: String,
+    pub tag: Option<String>,
+    pub weight: f32,
+}
+impl Record {
+    fn plain(text: String) -> Record {
+        Record {
+            text,
+            tag: None,
+            weight: 1.0,
+        }
+    }
+}
+
+// Ok(None) for a record that is malformed in the input format
+pub type RecordResult = io::Result<Option<Record>>;
+
+// a csv weight that is not a finite number of at least 0, on `line` of the
+// records read. it fails the reading instead of skipping the record, the
+// weight multiplies the learning rate
+#[derive(Debug)]
+struct BadWeight {
+    line: u64,
+    weight: String,
+}
+impl fmt::Display for BadWeight {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid weight {} on line {}", self.weight, self.line)
+    }
+}
+impl error::Error for BadWeight {}
+
+// the error of reading the records of `filename` from byte `start`, with the
+// line of a bad weight counted from the beginning of the file
+pub fn record_error(err: io::Error, filename: &str, start: u64) -> W2vError {
+    let bad = match err.get_ref().and_then(|e| e.downcast_ref::<BadWeight>()) {
+        Some(bad) => bad,
+        None => return W2vError::File(err),
+    };
+    let lines_before = || -> io::Result<u64> {
+        if start == 0 {
+            return Ok(0);
+        }
+        let mut reader = BufReader::new(File::open(filename)?.take(start));
+        let mut lines = 0;
+        loop {
+            let buf = reader.fill_buf()?;
+            if buf.is_empty() {
+                return Ok(lines);
+            }
+            lines += buf.iter().filter(|&&b| b == b'\n').count() as u64;
+            let len = buf.len();
+            reader.consume(len);
+        }
+    };
+    match lines_before() {
+        Ok(lines) => W2vError::InvalidInput(format!(
+            "invalid weight {} on line {} of {}",
+            bad.weight,
+            lines + bad.line,
+            filename
+        )),
+        Err(err) => W2vError::File(err),
+    }
+}
+
+impl Column {
+    // numbers select a column by index, counting from 0
+    pub fn parse(column: &str) -> Column {
+        match column.parse::<usize>() {
+            Ok(index) => Column::Index(index),
+            Err(_) => Column::Name(column.to_string()),
+        }
+    }
+    // the inverse of parse
+    pub fn name(&self) -> String {
+        match *self {
+            Column::Name(ref name) => name.clone(),
+            Column::Index(index) => index.to_string(),
+        }
+    }
+    fn resolve(&self, header: &StringRecord) -> Result<Column, W2vError> {
+        match *self {
+            Column::Name(ref name) => header
+                .iter()
+                .position(|h| h == name)
+                .map(Column::Index)
+                .ok_or_else(|| W2vError::InvalidInput(format!("no column named {}", name))),
+            Column::Index(index) => Ok(Column::Index(index)),
+        }
+    }
+    fn index(&self) -> Result<usize, W2vError> {
+        match *self {
+            Column::Index(index) => Ok(index),
+            Column::Name(ref name) => Err(W2vError::InvalidInput(format!(
+                "column {} can not be found without a header",
+                name
+            ))),
+        }
+    }
+}
+
+impl Default for CsvFormat {
+    fn default() -> CsvFormat {
+        CsvFormat {
+            delimiter: b',',
+            header: true,
+            text: Column::Name("text".to_string()),
+            tag: None,
+            weight: None,
+        }
+    }
+}
+
+impl CsvFormat {
+    fn with_header(&self, header: &StringRecord) -> Result<CsvFormat, W2vError> {
+        let resolve = |column: &Option<Column>| match *column {
+            Some(ref c) => c.resolve(header).map(Some),
+            None => Ok(None),
+        };
+        Ok(CsvFormat {
+            delimiter: self.delimiter,
+            header: self.header,
+            text: self.text.resolve(header)?,
+            tag: resolve(&self.tag)?,
+            weight: resolve(&self.weight)?,
+        })
+    }
+    fn reader<R: io::Read>(&self, reader: R) -> csv::Reader<R> {
+        ReaderBuilder::new()
+            .delimiter(self.delimiter)
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(reader)
+    }
+    fn record(
+        &self,
+        row: &ByteRecord,
+        text: usize,
+        tag: Option<usize>,
+        weight: Option<usize>,
+    ) -> RecordResult {
+        let field = |i: usize| row.get(i).and_then(|f| std::str::from_utf8(f).ok());
+        let weight = match weight.map(field) {
+            Some(Some(w)) => match w.trim().parse::<f32>() {
+                Ok(w) if w.is_finite() && w >= 0. => w,
+                _ => {
+                    let bad = BadWeight {
+                        line: row.position().map_or(0, |p| p.line()),
+                        weight: w.to_string(),
+                    };
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, bad));
+                }
+            },
+            Some(None) => return Ok(None),
+            None => 1.0,
+        };
+        let (text, tag) = match (field(text), tag.map(field)) {
+            (Some(text), None) => (text, None),
+            (Some(text), Some(Some(tag))) => (text, Some(tag.to_string())),
+            _ => return Ok(None),
+        };
+        Ok(Some(Record {
+            text: text.to_string(),
+            tag,
+            weight,
+        }))
+    }
+    // byte offsets splitting `filename` into `n_split` parts that start at
+    // record boundaries, the first one after the header. records may span
+    // several lines, so the file can not be split at arbitrary line ends
+    pub fn split_indices(&self, filename: &str, n_split: u64) -> Result<Vec<u64>, W2vError> {
+        let len = std::fs::metadata(filename)?.len();
+        let mut reader = self.reader(BufReader::with_capacity(10000, File::open(filename)?));
+        let mut row = ByteRecord::new();
+        if self.header {
+            reader.read_byte_record(&mut row)?;
+        }
+        let offset = len / n_split;
+        let mut bytes = vec![reader.position().byte()];
+        while (bytes.len() as u64) < n_split {
+            let pos = reader.position().byte();
+            if pos >= offset * bytes.len() as u64 {
+                bytes.push(pos);
+            } else if !reader.read_byte_record(&mut row)? {
+                bytes.push(len);
+            }
+        }
+        bytes.push(len);
+        Ok(bytes)
+    }
+
+    fn records<'a, R: BufRead + 'a>(
+        &self,
+        reader: R,
+        has_header: bool,
+    ) -> Result<Box<dyn Iterator<Item = RecordResult> + 'a>, W2vError> {
+        let mut reader = self.reader(reader);
+        let mut format = self.clone();
+        if has_header && self.header {
+            let mut header = StringRecord::new();
+            reader.read_record(&mut header)?;
+            format = self.with_header(&header)?;
+        }
+        let text = format.text.index()?;
+        let tag = format.tag.as_ref().map(Column::index).transpose()?;
+        let weight = format.weight.as_ref().map(Column::index).transpose()?;
+        Ok(Box::new(reader.into_byte_records().map(move |row| match row {
+            Ok(row) => format.record(&row, text, tag, weight),
+            Err(e) => match e.into_kind() {
+                csv::ErrorKind::Io(e) => Err(e),
+                _ => Ok(None),
+            },
+        })))
+    }
+}
+
+impl InputFormat {
+    // picks the format from the --format flag, or from the file name when
+    // the flag is not given
+    pub fn from_args(
+        filename: &str,
+        format: Option<&str>,
+        field: Option<&str>,
+        csv: CsvFormat,
+    ) -> InputFormat {
+        let format = format.unwrap_or_else(|| {
+            if filename.ends_with(".jsonl") || filename.ends_with(".ndjson") {
+                "jsonl"
+            } else if filename.ends_with(".csv") {
+                "csv"
+            } else if filename.ends_with(".tsv") {
+                "tsv"
+            } else if filename.contains("parquet") {
+                "parquet"
+            } else {
+                "text"
+            }
+        });
+        match format {
+            "jsonl" => InputFormat::Jsonl {
+                field: json_pointer(field.unwrap_or("text")),
+            },
+            "csv" => InputFormat::Csv(csv),
+            "tsv" => InputFormat::Csv(CsvFormat {
+                delimiter: b'\t',
+                ..csv
+            }),
+            "parquet" => InputFormat::Parquet,
+            _ => InputFormat::Text,
+        }
+    }
+
+    // the --format value giving this format
+    pub fn name(&self) -> &'static str {
+        match *self {
+            InputFormat::Text => "text",
+            InputFormat::Parquet => "parquet",
+            InputFormat::Jsonl { .. } => "jsonl",
+            InputFormat::Csv(ref csv) if csv.delimiter == b'\t' => "tsv",
+            InputFormat::Csv(_) => "csv",
+        }
+    }
+
+    // the same format with csv column names replaced by indices, read from
+    // the header of `filename`
+    pub fn resolve_columns(&self, filename: &str) -> Result<InputFormat, W2vError> {
+        match *self {
+            InputFormat::Csv(ref csv) if csv.header => {
+                let mut reader = csv.reader(File::open(filename)?);
+                let mut header = StringRecord::new();
+                reader.read_record(&mut header)?;
+                Ok(InputFormat::Csv(csv.with_header(&header)?))
+            }
+            _ => Ok(self.clone()),
+        }
+    }
+
+    // byte offsets splitting `filename` into `n_split` parts of whole
+    // records, for the threads counting and training on it
+    pub fn split_indices(&self, filename: &str, n_split: u64) -> Result<Vec<u64>, W2vError> {
+        match *self {
+            InputFormat::Csv(ref csv) => csv.split_indices(filename, n_split),
+            _ => file_split_indices(filename, n_split),
+        }
+    }
+
+    // records of `reader`; `has_header` tells whether it starts at the
+    // beginning of the file, where the csv header is
+    pub fn records<'a, R: BufRead + 'a>(
+        &self,
+        reader: R,
+        has_header: bool,
+    ) -> Result<Box<dyn Iterator<Item = RecordResult> + 'a>, W2vError> {
+        match *self {
+            InputFormat::Csv(ref csv) => csv.records(reader, has_header),
+            _ => {
+                let format = self.clone();
+                Ok(Box::new(reader.split(b'\n').map(move |line| {
+                    Ok(String::from_utf8(line?)
+                        .ok()
+                        .and_then(|line| format.line_record(line)))
+                })))
+            }
+        }
+    }
+
+    fn line_record(&self, line: String) -> Option<Record> {
+        match *self {
+            InputFormat::Jsonl { ref field } => {
+                if line.trim().is_empty() {
+                    return Some(Record::plain(line));
+                }
+                let value: Value = serde_json::from_str(&line).ok()?;
+                match value.pointer(field)? {
+                    Value::String(s) => Some(Record::plain(s.to_owned())),
+                    // already tokenized text
+                    Value::Array(tokens) => {
+                        let tokens = tokens
+                            .iter()
+                            .map(|t| t.as_str())
+                            .collect::<Option<Vec<&str>>>()?;
+                        Some(Record::plain(tokens.join(" ")))
+                    }
+                    _ => None,
+                }
+            }
+            _ => Some(Record::plain(line)),
+        }
+    }
+}
+
+// accepts both json pointers ("/fields/text") and dotted paths ("fields.text")
+fn json_pointer(field: &str) -> String {
+    if field.starts_with('/') {
+        return field.to_string();
+    }
+    field
+        .split('.')
+        .map(|key| format!("/{}", key.replace('~', "~0").replace('/', "~1")))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn texts(format: &CsvFormat, data: &[u8], has_header: bool) -> Vec<String> {
+        format
+            .records(Cursor::new(data), has_header)
+            .unwrap()
+            .map(|record| record.unwrap().unwrap().text)
+            .collect()
+    }
+
+    #[test]
+    fn splits_skip_quoted_line_ends() {
+        // most bytes are in quoted fields whose lines look like records
+        let mut data = String::from("id,text\n");
+        for i in 0..40 {
+            data += &format!(
+                "{},\"first {}\n{},\"\"not a record\"\"\nlast {}\"\n",
+                i, i, i, i
+            );
+        }
+        let path = std::env::temp_dir().join(format!("split-{}.csv", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, &data).unwrap();
+        let format = CsvFormat {
+            text: Column::Index(1),
+            ..CsvFormat::default()
+        };
+        let bytes = format.split_indices(path, 7);
+        std::fs::remove_file(path).unwrap();
+        let bytes = bytes.unwrap();
+        assert_eq!(bytes.len(), 8);
+        assert_eq!(*bytes.last().unwrap(), data.len() as u64);
+
+        let data = data.as_bytes();
+        let mut parts = Vec::new();
+        // the first split is after the header, as the threads read them
+        assert_eq!(bytes[0], "id,text\n".len() as u64);
+        for range in bytes.windows(2) {
+            let part = &data[range[0] as usize..range[1] as usize];
+            parts.extend(texts(&format, part, false));
+        }
+        let whole = texts(&format, data, true);
+        assert_eq!(whole.len(), 40);
+        assert_eq!(parts, whole);
+    }
+
+    fn jsonl_texts(field: &str, data: &str) -> Vec<Option<String>> {
+        let format =
+            InputFormat::from_args("corpus.jsonl", None, Some(field), CsvFormat::default());
+        format
+            .records(Cursor::new(data.as_bytes()), true)
+            .unwrap()
+            .map(|record| record.unwrap().map(|record| record.text))
+            .collect()
+    }
+
+    #[test]
+    fn jsonl_text_at_a_pointer_or_path() {
+        let data =
+            "{\"doc\": {\"a/b\": \"some text\"}}\n{\"doc\": {\"a/b\": [\"tok\", \"ens\"]}}\n";
+        let expected = [Some("some text".to_string()), Some("tok ens".to_string())];
+        assert_eq!(jsonl_texts("/doc/a~1b", data), expected);
+        assert_eq!(jsonl_texts("doc.a/b", data), expected);
+        assert_eq!(
+            jsonl_texts("text", "{\"text\": \"top level\"}\n"),
+            [Some("top level".to_string())]
+        );
+    }
+
+    #[test]
+    fn bad_jsonl_lines_are_skipped() {
+        let data = [
+            "{\"text\": \"kept\"",
+            "not json",
+            "{\"other\": \"no text field\"}",
+            "{\"text\": 3}",
+            "{\"text\": [\"mixed\", 1]}",
+            "{\"text\": null}",
+            "[\"text\"]",
+            "{\"text\": \"kept\"}",
+        ]
+        .join("\n");
+        let mut expected = vec![None; 7];
+        expected.push(Some("kept".to_string()));
+        assert_eq!(jsonl_texts("text", &data), expected);
+    }
+}
@@ -0,0 +1,220 @@
+use crate::corpus::InputFormat;
+use crate::{Dict, W2vError};
+use memmap2::Mmap;
+use std::fs::File;
+use std::io::{BufRead, BufWriter, Seek, SeekFrom, Write};
+use std::mem::size_of;
+use std::slice;
+
+// the corpus as vocabulary ids in native byte order, out of vocabulary words
+// dropped and every record followed by SENTENCE_END:
+// MAGIC, number of word ids as u64, then the u32 ids
+const MAGIC: &[u8; 8] = b"W2VIDS01";
+const HEADER_SIZE: usize = 16;
+pub const SENTENCE_END: u32 = u32::MAX;
+
+// the ids in a mapped cache file, or in memory when encoded from sentences
+// the caller already holds
+enum Ids {
+    Mapped(Mmap),
+    Memory(Vec<u32>),
+}
+
+pub struct CorpusCache {
+    ids: Ids,
+    ntokens: usize,
+}
+
+impl CorpusCache {
+    // encodes the records read from `input` with the ids of `dict` into
+    // `path` and maps it
+    pub fn create<R: BufRead>(
+        path: &str,
+        input: R,
+        format: &InputFormat,
+        dict: &Dict,
+    ) -> Result<CorpusCache, W2vError> {
+        if let InputFormat::Csv(ref csv) = *format {
+            if csv.tag.is_some() || csv.weight.is_some() {
+                return Err(W2vError::InvalidInput(
+                    "the corpus cache keeps no tags or weights".to_string(),
+                ));
+            }
+        }
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&0u64.to_ne_bytes())?;
+        let mut ids = Vec::new();
+        let mut ntokens = 0u64;
+        for record in format.records(input, true)? {
+            if let Some(record) = record? {
+                dict.encode_line(&record.text, &mut ids);
+                ntokens += ids.len() as u64;
+                ids.push(SENTENCE_END);
+                for id in &ids {
+                    writer.write_all(&id.to_ne_bytes())?;
+                }
+                ids.clear();
+            }
+        }
+        let mut file = writer.into_inner().map_err(|e| e.into_error())?;
+        file.seek(SeekFrom::Start(MAGIC.len() as u64))?;
+        file.write_all(&ntokens.to_ne_bytes())?;
+        drop(file);
+        CorpusCache::open(path)
+    }
+
+    // encodes already tokenized sentences with the ids of `dict` in memory
+    pub fn from_sentences<I, S, W>(sentences: I, dict: &Dict) -> CorpusCache
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<[W]>,
+        W: AsRef<str>,
+    {
+        let mut ids = Vec::new();
+        let mut ntokens = 0;
+        for sentence in sentences {
+            let start = ids.len();
+            dict.encode_tokens(sentence.as_ref(), &mut ids);
+            ntokens += ids.len() - start;
+            ids.push(SENTENCE_END);
+        }
+        ids.shrink_to_fit();
+        CorpusCache {
+            ids: Ids::Memory(ids),
+            ntokens,
+        }
+    }
+
+    pub fn open(path: &str) -> Result<CorpusCache, W2vError> {
+        let file = File::open(path)?;
+        let map = unsafe { Mmap::map(&file)? };
+        if map.len() < HEADER_SIZE
+            || &map[..MAGIC.len()] != MAGIC
+            || !(map.len() - HEADER_SIZE).is_multiple_of(size_of::<u32>())
+        {
+            return Err(W2vError::InvalidInput(format!("{} is not a corpus cache", path)));
+        }
+        let mut ntokens = [0u8; 8];
+        ntokens.copy_from_slice(&map[MAGIC.len()..HEADER_SIZE]);
+        Ok(CorpusCache {
+            ntokens: u64::from_ne_bytes(ntokens) as usize,
+            ids: Ids::Mapped(map),
+        })
+    }
+
+    #[inline]
+    pub fn ids(&self) -> &[u32] {
+        match self.ids {
+            Ids::Mapped(ref map) => {
+                let data = &map[HEADER_SIZE..];
+                // the map is page aligned and the header keeps the ids aligned
+                unsafe {
+                    slice::from_raw_parts(data.as_ptr() as *const u32, data.len() / size_of::<u32>())
+                }
+            }
+            Ids::Memory(ref ids) => ids,
+        }
+    }
+
+    // number of word ids, sentence ends not included
+    #[inline]
+    pub fn ntokens(&self) -> usize {
+        self.ntokens
+    }
+
+    // id offsets splitting the cache into `n_split` parts of whole sentences
+    pub fn split_indices(&self, n_split: usize) -> Vec<usize> {
+        let ids = self.ids();
+        let offset = ids.len() / n_split;
+        let mut splits = vec![0];
+        for i in 1..n_split {
+            let start = (offset * i).max(splits[i - 1]);
+            let end = ids[start..]
+                .iter()
+                .position(|&id| id == SENTENCE_END)
+                .map_or(ids.len(), |p| start + p + 1);
+            splits.push(end);
+        }
+        splits.push(ids.len());
+        splits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Tokenizer;
+    use std::io::Cursor;
+
+    const TEXT: &str = "the cat sat\n\nthe dog sat on the mat\nan unknown line\ncat\n";
+
+    fn dict() -> Dict {
+        let words = ["the", "sat", "cat", "dog", "on", "mat"];
+        Dict::from_saved(
+            words.iter().map(|w| (w.to_string(), 1)).collect(),
+            Tokenizer::default(),
+        )
+    }
+
+    fn sentences() -> Vec<Vec<&'static str>> {
+        TEXT.lines()
+            .map(|line| line.split_whitespace().collect())
+            .collect()
+    }
+
+    // the sentences of the text encoded again, ended like in the cache
+    fn text_ids(dict: &Dict) -> Vec<u32> {
+        let mut ids = Vec::new();
+        for line in TEXT.lines() {
+            dict.encode_line(line, &mut ids);
+            ids.push(SENTENCE_END);
+        }
+        ids
+    }
+
+    #[test]
+    fn reopened_cache_reads_like_the_text() {
+        let dict = dict();
+        let path = std::env::temp_dir().join(format!("corpus-cache-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let created = CorpusCache::create(path, Cursor::new(TEXT), &InputFormat::Text, &dict);
+        let reopened = CorpusCache::open(path);
+        std::fs::remove_file(path).unwrap();
+        let (created, reopened) = (created.unwrap(), reopened.unwrap());
+
+        let expected = text_ids(&dict);
+        let ntokens = expected.iter().filter(|&&id| id != SENTENCE_END).count();
+        assert_eq!(created.ids(), &expected[..]);
+        assert_eq!(reopened.ids(), &expected[..]);
+        assert_eq!(reopened.ntokens(), ntokens);
+
+        let memory = CorpusCache::from_sentences(sentences(), &dict);
+        assert_eq!(memory.ids(), &expected[..]);
+        assert_eq!(memory.ntokens(), ntokens);
+    }
+
+    #[test]
+    fn splits_end_sentences() {
+        let cache = CorpusCache::from_sentences(sentences(), &dict());
+        let ids = cache.ids();
+        for n in 1..6 {
+            let splits = cache.split_indices(n);
+            assert_eq!(splits.len(), n + 1);
+            assert_eq!((splits[0], splits[n]), (0, ids.len()));
+            for &split in &splits[1..n] {
+                assert!(split == ids.len() || ids[split - 1] == SENTENCE_END);
+            }
+        }
+    }
+
+    #[test]
+    fn other_files_are_not_caches() {
+        let path = std::env::temp_dir().join(format!("not-a-cache-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "W2VIDS00 and some text").unwrap();
+        let opened = CorpusCache::open(path);
+        std::fs::remove_file(path).unwrap();
+        assert!(opened.is_err());
+    }
+}
@@ -1,13 +1,20 @@
 use super::W2vError;
-use crate::NEGATIVE_TABLE_SIZE;
+use crate::corpus::{record_error, InputFormat, Record, RecordResult};
+use crate::events::{Event, Observer};
+use crate::file_utils::get_file_range_reader;
+use crate::tokenizer::Tokenizer;
+use crate::Argument;
+use crate::sampler::AliasTable;
 use rand::distributions::{IndependentSample, Range};
-use rand::{thread_rng, Rng};
+use rand::Rng;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::prelude::*;
-use std::io::{stdout, BufReader};
-use std::ops::Index;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::thread;
 
 use parquet::file::reader::{FileReader, SerializedFileReader};
 
@@ -18,6 +25,13 @@ pub struct Dict {
     pub ntokens: usize,
     size: usize,
     discard_table: Vec<f32>,
+    tokenizer: Tokenizer,
+    // document tags, the last ntags indices. they are looked up apart from
+    // the words, a word of the text is never a tag
+    tag2ent: HashMap<String, Entry>,
+    ntags: usize,
+    // index of UNK_TOKEN, trained for the words left out of the vocabulary
+    unk: Option<usize>,
 }
 #[derive(Debug)]
 pub struct Entry {
@@ -25,34 +39,246 @@ pub struct Entry {
     pub count: u32,
 }
 
-const NEG_POW: f64 = 0.75;
+// result of the counting pass over a corpus
+struct WordCounts {
+    words: HashMap<String, Entry>,
+    ntokens: usize,
+    // lines that could not be parsed in the input format
+    skipped: usize,
+    // document tags, counted apart from the words and kept in the vocabulary
+    // regardless of min_count
+    tags: HashMap<String, Entry>,
+    tokenizer: Tokenizer,
+    // words are pruned while counting to keep at most max_size of them,
+    // dropping the ones seen less than min_reduce times
+    max_size: Option<usize>,
+    min_reduce: u32,
+    // tokens already added to the count shared by all counting threads
+    reported: usize,
+}
+impl WordCounts {
+    // counts of one of `parts` parts of the corpus counted at once, which
+    // share max_vocab_size and the memory reserved up front
+    fn new(args: &Argument, parts: usize) -> WordCounts {
+        let parts = parts.max(1);
+        let max_size = args.max_vocab_size.map(|max_size| (max_size / parts).max(1));
+        let capacity = max_size.unwrap_or(usize::MAX).min((2 << 20) / parts);
+        WordCounts {
+            words: HashMap::with_capacity(capacity),
+            ntokens: 0,
+            skipped: 0,
+            tags: HashMap::new(),
+            tokenizer: args.tokenizer.clone(),
+            max_size,
+            min_reduce: 1,
+            reported: 0,
+        }
+    }
+    // drops the least frequent words, raising the bar until they fit,
+    // like gensim's max_vocab_size. tags are never pruned
+    fn prune(&mut self, max_size: usize) {
+        while self.words.len() > max_size {
+            self.min_reduce += 1;
+            let min_reduce = self.min_reduce;
+            self.words.retain(|_, e| e.count >= min_reduce);
+        }
+    }
+    fn add_record(&mut self, record: &Record, size: &mut usize) {
+        self.add_line(&record.text, size);
+        if let Some(ref tag) = record.tag {
+            Dict::add_to_dict(&mut self.tags, tag, size);
+        }
+    }
+    fn add_line(&mut self, line: &str, size: &mut usize) {
+        let (words, ntokens) = (&mut self.words, &mut self.ntokens);
+        self.tokenizer.tokenize(line, |word| {
+            Dict::add_to_dict(words, word, size);
+            *ntokens += 1;
+        });
+        self.prune_to_max();
+    }
+    // counts the words of an already tokenized sentence as they are
+    fn add_tokens<W: AsRef<str>>(&mut self, tokens: &[W], size: &mut usize) {
+        for word in tokens {
+            Dict::add_to_dict(&mut self.words, word.as_ref(), size);
+        }
+        self.ntokens += tokens.len();
+        self.prune_to_max();
+    }
+    fn prune_to_max(&mut self) {
+        if let Some(max_size) = self.max_size {
+            if self.words.len() > max_size {
+                self.prune(max_size);
+            }
+        }
+    }
+    // adds the tokens counted since the last call to `read`, the count of
+    // all threads, reporting it every million words
+    fn report(&mut self, read: &AtomicUsize, observer: &dyn Observer) {
+        let new = self.ntokens - self.reported;
+        if new < 10000 {
+            return;
+        }
+        self.reported = self.ntokens;
+        let before = read.fetch_add(new, Ordering::Relaxed);
+        if (before + new) / 1000000 > before / 1000000 {
+            observer.on_event(&Event::WordsRead(before + new));
+        }
+    }
+    // adds the counts of another part of the corpus
+    fn merge(&mut self, other: WordCounts) {
+        for (word, e) in other.words {
+            self.words
+                .entry(word)
+                .or_insert(Entry { index: 0, count: 0 })
+                .count += e.count;
+        }
+        self.ntokens += other.ntokens;
+        self.skipped += other.skipped;
+        for (tag, e) in other.tags {
+            self.tags
+                .entry(tag)
+                .or_insert(Entry { index: 0, count: 0 })
+                .count += e.count;
+        }
+        self.min_reduce = self.min_reduce.max(other.min_reduce);
+        self.prune_to_max();
+    }
+}
+
+// vector of the words below min_count or past max_final_vocab with Oov::Unk
+pub const UNK_TOKEN: &str = "<unk>";
+
+// what queries do with words that are not in the vocabulary
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Oov {
+    // fail with W2vError::OutOfVocabulary
+    Error,
+    // leave the word out
+    Skip,
+    // use a vector of zeros
+    Zero,
+    // use the vector of UNK_TOKEN, trained in place of the rare words
+    Unk,
+}
+
+impl Oov {
+    pub fn parse(s: &str) -> Option<Oov> {
+        match s {
+            "error" => Some(Oov::Error),
+            "skip" => Some(Oov::Skip),
+            "zero" => Some(Oov::Zero),
+            "unk" => Some(Oov::Unk),
+            _ => None,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Oov::Error => "error",
+            Oov::Skip => "skip",
+            Oov::Zero => "zero",
+            Oov::Unk => "unk",
+        }
+    }
+}
+// first line of a vocabulary file, followed by the number of corpus tokens
+const VOCAB_HEADER: &str = "#word2vec-vocab";
+const VOCAB_TAG: &str = "tag";
+
+// words may hold any character but the separators of the vocabulary file
+pub(crate) fn escape_word(word: &str) -> String {
+    let mut escaped = String::with_capacity(word.len());
+    for c in word.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub(crate) fn unescape_word(escaped: &str) -> Option<String> {
+    let mut word = String::with_capacity(escaped.len());
+    let mut chars = escaped.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            word.push(c);
+            continue;
+        }
+        match chars.next()? {
+            '\\' => word.push('\\'),
+            't' => word.push('\t'),
+            'n' => word.push('\n'),
+            'r' => word.push('\r'),
+            _ => return None,
+        }
+    }
+    Some(word)
+}
 
 impl Dict {
-    fn new() -> Dict {
+    fn new(tokenizer: Tokenizer) -> Dict {
         Dict {
             word2ent: HashMap::new(),
             idx2word: Vec::new(),
             ntokens: 0,
             size: 0,
             discard_table: Vec::new(),
+            tokenizer,
+            tag2ent: HashMap::new(),
+            ntags: 0,
+            unk: None,
         }
     }
-    pub fn init_negative_table(&self) -> Arc<Vec<usize>> {
-        let mut negative_table = Vec::new();
-        let counts = self.counts();
-        let mut z = 0f64;
-        for c in &counts {
-            z += (*c as f64).powf(NEG_POW);
+    // samples words in proportion to count^neg_pow, or to the weights of
+    // the noise_weights file when given. tags are never drawn
+    pub fn negative_sampler(&self, args: &Argument) -> Result<Arc<AliasTable>, W2vError> {
+        let weights = match args.noise_weights {
+            Some(ref filename) => self.load_noise_weights(filename)?,
+            None => self
+                .counts()
+                .into_iter()
+                .take(self.nwords())
+                .map(|c| (c as f64).powf(args.neg_pow as f64))
+                .collect(),
+        };
+        // a negative sample is drawn again until it is not the target, which
+        // never ends when the target is the only word that can be drawn
+        let drawn = weights.iter().filter(|&&w| w > 0.).count();
+        if drawn < 2 {
+            let source = args.noise_weights.as_deref().unwrap_or("the vocabulary");
+            return Err(W2vError::InvalidInput(format!(
+                "negative samples need at least 2 words of positive weight, {} has {}",
+                source, drawn
+            )));
         }
-        for (idx, i) in counts.into_iter().enumerate() {
-            let c = (i as f64).powf(NEG_POW);
-            for _ in 0..(c * NEGATIVE_TABLE_SIZE as f64 / z) as usize {
-                negative_table.push(idx as usize);
+        Ok(Arc::new(AliasTable::new(&weights)?))
+    }
+
+    // `word<TAB>weight` lines, words escaped like in the vocabulary file.
+    // words missing from the file are never drawn, words missing from the
+    // vocabulary are ignored
+    fn load_noise_weights(&self, filename: &str) -> Result<Vec<f64>, W2vError> {
+        let mut weights = vec![0f64; self.nwords()];
+        for line in BufReader::new(File::open(filename)?).lines() {
+            let line = line?;
+            let invalid = || {
+                W2vError::InvalidInput(format!("bad line in noise weights {}: {}", filename, line))
+            };
+            let (word, weight) = line.split_once('\t').ok_or_else(invalid)?;
+            let word = unescape_word(word).ok_or_else(invalid)?;
+            let weight = weight.parse::<f64>().map_err(|_| invalid())?;
+            if !weight.is_finite() || weight < 0. {
+                return Err(invalid());
+            }
+            if let Some(index) = self.get_idx(&word) {
+                weights[index] = weight;
             }
         }
-        let mut rng = thread_rng();
-        rng.shuffle(&mut negative_table);
-        Arc::new(negative_table)
+        Ok(weights)
     }
 
     fn add_to_dict(words: &mut HashMap<String, Entry>, word: &str, size: &mut usize) {
@@ -69,69 +295,174 @@ impl Dict {
             .count += 1;
     }
     #[inline(always)]
+    pub fn tokenizer(&self) -> &Tokenizer {
+        &self.tokenizer
+    }
+    #[inline(always)]
     pub fn nsize(&self) -> usize {
         self.size
     }
+    // the words, the indices below those of the tags
     #[inline(always)]
-    pub fn get_idx(&self, word: &str) -> usize {
-        self.word2ent[word].index
+    pub fn nwords(&self) -> usize {
+        self.size - self.ntags
     }
-    #[inline(always)]
-    pub fn get_word(&self, idx: usize) -> String {
-        self.idx2word[idx].clone()
+    #[inline]
+    pub fn get_idx(&self, word: &str) -> Option<usize> {
+        self.word2ent.get(word).map(|e| e.index)
     }
+    // index of a word of a query, tokenized like the corpus was
     #[inline]
-    pub fn get_entry(&self, word: &str) -> &Entry {
-        self.word2ent.index(word)
+    pub fn query_idx(&self, word: &str) -> Option<usize> {
+        self.get_idx(&self.tokenizer.word(word))
     }
+    // index of the document vector of `tag`
+    #[inline]
+    pub fn get_tag_idx(&self, tag: &str) -> Option<usize> {
+        self.tag2ent.get(tag).map(|e| e.index)
+    }
+    #[inline]
+    pub fn get_word(&self, idx: usize) -> Option<&str> {
+        self.idx2word.get(idx).map(String::as_str)
+    }
+    #[inline]
+    pub fn get_entry(&self, word: &str) -> Option<&Entry> {
+        self.word2ent.get(word)
+    }
+    // index of the <unk> vector, when trained
+    #[inline]
+    pub fn unk(&self) -> Option<usize> {
+        self.unk
+    }
+    // counts of the words then of the tags, by index
     pub fn counts(&self) -> Vec<u32> {
-        let mut counts_ = vec![0; self.idx2word.len()];
-        for (i, v) in self.idx2word.iter().enumerate() {
-            counts_[i] = self.word2ent[v].count;
+        (0..self.idx2word.len()).map(|i| self.entry_at(i).count).collect()
+    }
+    fn entry_at(&self, idx: usize) -> &Entry {
+        let word = &self.idx2word[idx];
+        match self.word2ent.get(word) {
+            Some(e) if e.index == idx => e,
+            _ => &self.tag2ent[word],
         }
-        counts_
     }
-    pub fn read_line(&self, line: &mut String, lines: &mut Vec<usize>) -> usize {
+    pub fn read_line<R: Rng>(&self, line: &str, lines: &mut Vec<usize>, rng: &mut R) -> usize {
         let mut i = 0;
-        let mut rng = thread_rng();
         let between = Range::new(0., 1.);
-        for word in line.split_whitespace() {
+        self.tokenizer.tokenize(line, |word| {
             i += 1;
-            match self.word2ent.get(word) {
-                Some(e) => {
-                    if self.discard_table[e.index] > between.ind_sample(&mut rng) {
-                        lines.push(e.index);
-                    }
+            if let Some(index) = self.get_idx(word).or(self.unk) {
+                if self.discard_table[index] > between.ind_sample(rng) {
+                    lines.push(index);
                 }
-                None => {}
             }
-        }
+        });
         i
     }
+    // like read_line for a sentence of the corpus cache
+    pub fn read_ids<R: Rng>(&self, ids: &[u32], lines: &mut Vec<usize>, rng: &mut R) -> usize {
+        let between = Range::new(0., 1.);
+        for &id in ids {
+            if self.discard_table[id as usize] > between.ind_sample(rng) {
+                lines.push(id as usize);
+            }
+        }
+        ids.len()
+    }
+    // ids of the words of `line` in the vocabulary
+    pub fn encode_line(&self, line: &str, ids: &mut Vec<u32>) {
+        self.tokenizer.tokenize(line, |word| self.encode_word(word, ids));
+    }
+    // ids of the words of an already tokenized sentence in the vocabulary
+    pub fn encode_tokens<W: AsRef<str>>(&self, tokens: &[W], ids: &mut Vec<u32>) {
+        for word in tokens {
+            self.encode_word(word.as_ref(), ids);
+        }
+    }
+    #[inline]
+    fn encode_word(&self, word: &str, ids: &mut Vec<u32>) {
+        if let Some(index) = self.get_idx(word).or(self.unk) {
+            ids.push(index as u32);
+        }
+    }
 
-    fn words_from_text_file(input_file: File) -> (HashMap<String, Entry>, usize) {
-        let mut reader = BufReader::with_capacity(10000, input_file);
-        let mut buf_str = String::with_capacity(5000);
-        let mut words: HashMap<String, Entry> = HashMap::with_capacity(2 << 20);
-        let (mut ntokens, mut size) = (0, 0);
-        while reader.read_line(&mut buf_str).unwrap() > 0 {
-            for word in buf_str.split_whitespace() {
-                Dict::add_to_dict(&mut words, word, &mut size);
-                ntokens += 1;
-                if ntokens % 1000000 == 0 {
-                    print!("\rRead {}M words", ntokens / 1000000);
-                    stdout().flush().ok().expect("Could not flush stdout");
-                }
+    // counts the byte ranges the training threads get, one thread each, and
+    // merges their counts. the threads share max_vocab_size, so that all of
+    // them together hold no more words than one would
+    fn words_from_text_file(
+        filename: &str,
+        args: &Argument,
+        observer: &dyn Observer,
+    ) -> Result<WordCounts, W2vError> {
+        let format = args.format.resolve_columns(filename)?;
+        let splits = format.split_indices(filename, args.nthreads as u64)?;
+        let nparts = splits.len() - 1;
+        let read = AtomicUsize::new(0);
+        let parts = thread::scope(|s| {
+            let handles: Vec<_> = splits
+                .windows(2)
+                .map(|w| {
+                    let (start, end, format, read) = (w[0], w[1], &format, &read);
+                    s.spawn(move || {
+                        let reader = get_file_range_reader(filename, start, end)?;
+                        let records = format.records(reader, start == 0)?;
+                        let counts = WordCounts::new(args, nparts);
+                        Self::count_records(records, (filename, start), counts, read, observer)
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .collect::<Result<Vec<WordCounts>, W2vError>>()
+        })?;
+        let mut parts = parts.into_iter();
+        let mut counts = parts.next().unwrap_or_else(|| WordCounts::new(args, 1));
+        counts.max_size = args.max_vocab_size;
+        for part in parts {
+            counts.merge(part);
+        }
+        Ok(counts)
+    }
+
+    fn words_from_reader<R: BufRead>(
+        reader: R,
+        args: &Argument,
+        observer: &dyn Observer,
+    ) -> Result<WordCounts, W2vError> {
+        let records = args.format.records(reader, true)?;
+        let read = AtomicUsize::new(0);
+        Self::count_records(records, (&args.input, 0), WordCounts::new(args, 1), &read, observer)
+    }
+
+    // adds the records to `counts`. `from` is the file they are read from and
+    // their first byte
+    fn count_records<I>(
+        records: I,
+        from: (&str, u64),
+        mut counts: WordCounts,
+        read: &AtomicUsize,
+        observer: &dyn Observer,
+    ) -> Result<WordCounts, W2vError>
+    where
+        I: Iterator<Item = RecordResult>,
+    {
+        let mut size = 0;
+        for record in records {
+            match record.map_err(|err| record_error(err, from.0, from.1))? {
+                Some(record) => counts.add_record(&record, &mut size),
+                None => counts.skipped += 1,
             }
-            buf_str.clear();
+            counts.report(read, observer);
         }
-        (words, ntokens)
+        Ok(counts)
     }
 
     fn words_from_parquet_file(
         input_file: File,
-        max_rows: Option<usize>,
-    ) -> (HashMap<String, Entry>, usize) {
+        args: &Argument,
+        observer: &dyn Observer,
+    ) -> WordCounts {
+        let max_rows = args.max_rows;
         let reader = SerializedFileReader::new(input_file).unwrap();
         let parquet_metadata = reader.metadata();
 
@@ -141,98 +472,422 @@ impl Dict {
         //    &mut std::io::stdout(),
         //    &parquet_metadata.file_metadata().schema(),
         //);
-        let mut words: HashMap<String, Entry> = HashMap::with_capacity(2 << 20);
-        let (mut ntokens, mut size) = (0, 0);
-
-        let rows = parquet_metadata.file_metadata().num_rows();
-        let fields = parquet_metadata.file_metadata().schema().get_fields();
-
-        println!("");
-        println!("Parquet dataset column names:");
-
-        // dupa debug
-        for (order, column) in fields.iter().enumerate() {
-            let name = column.name();
-            println!("#: {} | Name: {}", order, name);
-        }
-        println!("using rows {}", max_rows.unwrap_or(0));
+        let mut counts = WordCounts::new(args, 1);
+        let mut size = 0;
+        let read = AtomicUsize::new(0);
 
         for (i, row) in reader.into_iter().enumerate() {
             let line = row.to_string();
-            for word in line.split_whitespace() {
-                Dict::add_to_dict(&mut words, word, &mut size);
-                ntokens += 1;
-                if ntokens % 1000000 == 0 {
-                    print!("\rRead {}M words", ntokens / 1000000);
-                    stdout().flush().ok().expect("Could not flush stdout");
-                }
-            }
+            counts.add_line(&line, &mut size);
+            counts.report(&read, observer);
             let do_break = max_rows.map_or(false, |m| m == i);
             if do_break {
                 break;
             }
         }
-        (words, ntokens)
+        counts
     }
 
     fn words_from_file(
         filename: &str,
-        max_rows: Option<usize>,
-    ) -> Result<(HashMap<String, Entry>, usize), W2vError> {
-        match File::open(filename) {
-            Ok(input_file) => Ok({
-                if filename.contains("parquet") {
-                    Self::words_from_parquet_file(input_file, max_rows)
-                } else {
-                    Self::words_from_text_file(input_file)
-                }
-            }),
-            _ => Err(W2vError::RuntimeError),
+        args: &Argument,
+        observer: &dyn Observer,
+    ) -> Result<WordCounts, W2vError> {
+        match args.format {
+            InputFormat::Parquet => match File::open(filename) {
+                Ok(input_file) => Ok(Self::words_from_parquet_file(input_file, args, observer)),
+                _ => Err(W2vError::RuntimeError),
+            },
+            _ => Self::words_from_text_file(filename, args, observer),
         }
     }
 
+    // writes the words and their counts, most frequent first, as
+    // `word<TAB>count` lines with tags marked by a third column. the
+    // tokenizer goes to `filename.tokenizer`
+    pub fn save_vocab(&self, filename: &str) -> Result<(), W2vError> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        writeln!(writer, "{}\t{}", VOCAB_HEADER, self.ntokens)?;
+        for (i, word) in self.idx2word.iter().enumerate() {
+            write!(writer, "{}\t{}", escape_word(word), self.entry_at(i).count)?;
+            if i >= self.nwords() {
+                write!(writer, "\t{}", VOCAB_TAG)?;
+            }
+            writeln!(writer)?;
+        }
+        writer.flush()?;
+        self.tokenizer.save(&format!("{}.tokenizer", filename))
+    }
+
+    // a vocabulary written by save_vocab, with min_count and max_final_vocab
+    // of `args` applied again. the saved tokenizer replaces the one of
+    // `args`, training has to split words the way they were counted
+    pub fn load_vocab(
+        filename: &str,
+        args: &Argument,
+        observer: &dyn Observer,
+    ) -> Result<Dict, W2vError> {
+        let mut counts = WordCounts::new(args, 1);
+        let tokenizer = format!("{}.tokenizer", filename);
+        if Path::new(&tokenizer).exists() {
+            counts.tokenizer = Tokenizer::load(&tokenizer)?;
+        }
+        let mut lines = BufReader::new(File::open(filename)?).lines();
+        let invalid = |line: &str| {
+            W2vError::InvalidInput(format!("bad line in vocabulary {}: {}", filename, line))
+        };
+        let header = lines.next().transpose()?.unwrap_or_default();
+        counts.ntokens = match header.split_once('\t') {
+            Some((VOCAB_HEADER, ntokens)) => ntokens.parse().map_err(|_| invalid(&header))?,
+            _ => return Err(invalid(&header)),
+        };
+        // the header is line 1
+        for (n, line) in (2..).zip(lines) {
+            let line = line?;
+            let mut fields = line.split('\t');
+            let word = fields.next().and_then(unescape_word);
+            let count = fields.next().and_then(|c| c.parse::<u32>().ok());
+            let (word, count) = match (word, count) {
+                (Some(word), Some(count)) => (word, count),
+                _ => return Err(invalid(&line)),
+            };
+            let entries = match fields.next() {
+                Some(VOCAB_TAG) => &mut counts.tags,
+                None => &mut counts.words,
+                Some(_) => return Err(invalid(&line)),
+            };
+            if entries.contains_key(&word) {
+                return Err(W2vError::InvalidInput(format!(
+                    "duplicate word {} on line {} of vocabulary {}",
+                    word, n, filename
+                )));
+            }
+            entries.insert(word, Entry { index: 0, count });
+        }
+        Ok(Self::from_counts(counts, args, observer))
+    }
+
+    // the vocabulary of a saved model, words with their counts in index
+    // order. it looks words up but is not trained from. the tags after the
+    // words are not marked, a tag named like a word is found by index only
+    pub fn from_saved(words: Vec<(String, u32)>, tokenizer: Tokenizer) -> Dict {
+        let mut dict = Dict::new(tokenizer);
+        for (index, (word, count)) in words.into_iter().enumerate() {
+            dict.ntokens += count as usize;
+            let entries = if dict.word2ent.contains_key(&word) {
+                &mut dict.tag2ent
+            } else {
+                &mut dict.word2ent
+            };
+            entries.insert(word.clone(), Entry { index, count });
+            dict.idx2word.push(word);
+        }
+        dict.size = dict.idx2word.len();
+        dict.unk = dict.get_idx(UNK_TOKEN);
+        dict
+    }
+
+    // counts the words of `filename` with the vocabulary settings of `args`
     pub fn new_from_file(
         filename: &str,
-        min_count: u32,
-        threshold: f32,
-        verbose: bool,
-        max_rows: Option<usize>,
+        args: &Argument,
+        observer: &dyn Observer,
     ) -> Result<Dict, W2vError> {
-        let mut dict = Dict::new();
-        let (words, ntokens) = Self::words_from_file(filename, max_rows)?;
+        let counts = Self::words_from_file(filename, args, observer)?;
+        Ok(Self::from_counts(counts, args, observer))
+    }
+
+    pub fn new_from_reader<R: BufRead>(
+        reader: R,
+        args: &Argument,
+        observer: &dyn Observer,
+    ) -> Result<Dict, W2vError> {
+        let counts = Self::words_from_reader(reader, args, observer)?;
+        Ok(Self::from_counts(counts, args, observer))
+    }
+
+    // counts the words of sentences tokenized by the caller, which are not
+    // tokenized again
+    pub fn new_from_sentences<I, S, W>(sentences: I, args: &Argument, observer: &dyn Observer) -> Dict
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<[W]>,
+        W: AsRef<str>,
+    {
+        let mut counts = WordCounts::new(args, 1);
+        let (mut size, read) = (0, AtomicUsize::new(0));
+        for sentence in sentences {
+            counts.add_tokens(sentence.as_ref(), &mut size);
+            counts.report(&read, observer);
+        }
+        Self::from_counts(counts, args, observer)
+    }
+
+    fn from_counts(counts: WordCounts, args: &Argument, observer: &dyn Observer) -> Dict {
+        let (min_count, threshold) = (args.min_count, args.threshold);
+        let mut dict = Dict::new(counts.tokenizer);
+        let ntokens = counts.ntokens;
+        let mut words: Vec<(String, Entry)> = counts.words.into_iter().collect();
+        let mut tags: Vec<(String, Entry)> = counts.tags.into_iter().collect();
+        // indices follow descending counts, ties broken by the word, so the
+        // same corpus always gives the same ids and the most frequent words
+        // come first. tags go after all words
+        let by_count = |a: &(String, Entry), b: &(String, Entry)| {
+            b.1.count.cmp(&a.1.count).then_with(|| a.0.cmp(&b.0))
+        };
+        words.sort_unstable_by(by_count);
+        tags.sort_unstable_by(by_count);
+        dict.ntags = tags.len();
+        let keep = words
+            .iter()
+            .take_while(|(_, v)| v.count >= min_count)
+            .count()
+            .min(args.max_final_vocab.unwrap_or(usize::MAX));
+        let dropped = words.split_off(keep);
+        if args.oov == Oov::Unk {
+            // the words left out are trained as <unk>
+            let count = dropped
+                .iter()
+                .fold(0u32, |count, (_, v)| count.saturating_add(v.count));
+            match words.iter_mut().find(|(k, _)| k == UNK_TOKEN) {
+                Some((_, v)) => v.count = v.count.saturating_add(count),
+                None => words.push((UNK_TOKEN.to_string(), Entry { index: 0, count })),
+            }
+            words.sort_unstable_by(by_count);
+        }
         let mut size = 0;
-        let word2ent: HashMap<String, Entry> = words
-            .into_iter()
-            .filter(|&(_, ref v)| v.count >= min_count)
-            .map(|(k, mut v)| {
-                v.index = size;
-                size += 1;
-                (k, v)
-            })
-            .collect();
-        dict.word2ent = word2ent;
-        dict.word2ent.shrink_to_fit();
-        dict.idx2word = vec!["".to_string(); dict.word2ent.len()];
-        for (k, v) in &dict.word2ent {
+        let mut index = |entries: Vec<(String, Entry)>| -> HashMap<String, Entry> {
+            entries
+                .into_iter()
+                .map(|(k, mut v)| {
+                    v.index = size;
+                    size += 1;
+                    (k, v)
+                })
+                .collect()
+        };
+        dict.word2ent = index(words);
+        dict.tag2ent = index(tags);
+        if args.oov == Oov::Unk {
+            dict.unk = dict.get_idx(UNK_TOKEN);
+        }
+        dict.idx2word = vec!["".to_string(); size];
+        for (k, v) in dict.word2ent.iter().chain(&dict.tag2ent) {
             dict.idx2word[v.index] = k.to_string();
         }
-        dict.idx2word.shrink_to_fit();
         dict.size = size;
         dict.ntokens = ntokens;
-        if verbose {
-            println!("\rRead {} M words", (ntokens / 1000000));
-            println!("\r{} unique words in total", size);
-        }
+        observer.on_event(&Event::VocabBuilt {
+            words: size,
+            tokens: ntokens,
+            skipped: counts.skipped,
+            pruned_below: Some(counts.min_reduce).filter(|&m| m > 1),
+        });
         dict.init_discard(threshold);
-        Ok(dict)
+        dict
     }
     fn init_discard(&mut self, threshold: f32) {
         let size = self.nsize();
         self.discard_table.reserve_exact(size);
         for i in 0..self.nsize() {
-            let f = self.word2ent[&self.idx2word[i]].count as f32 / self.ntokens as f32;
+            let f = self.entry_at(i).count as f32 / self.ntokens as f32;
             self.discard_table
                 .push((threshold / f).sqrt() + threshold / f);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ArgumentBuilder, Command};
+    use std::fs;
+    use std::sync::Mutex;
+
+    fn vocab_args() -> Argument {
+        ArgumentBuilder::new(String::new(), Command::Vocab)
+            .output("vocab".to_string())
+            .min_count(1)
+            .build()
+            .unwrap()
+    }
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn escaped_words_round_trip() {
+        let words = ["tab\there", "new\nline", "back\\slash", "\\t", "cr\r", "plain"];
+        let saved: Vec<(String, u32)> = words
+            .iter()
+            .enumerate()
+            .map(|(i, w)| (w.to_string(), 10 - i as u32))
+            .collect();
+        let dict = Dict::from_saved(saved, Tokenizer::default());
+        let path = temp_path("escaped.vocab");
+        dict.save_vocab(&path).unwrap();
+        let loaded = Dict::load_vocab(&path, &vocab_args(), &());
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(format!("{}.tokenizer", path)).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.idx2word, dict.idx2word);
+        assert_eq!(loaded.counts(), dict.counts());
+        assert_eq!(loaded.ntokens, dict.ntokens);
+    }
+
+    #[test]
+    fn unescape_rejects_unknown_escapes() {
+        assert_eq!(unescape_word("a\\\\b").as_deref(), Some("a\\b"));
+        assert_eq!(unescape_word("a\\x"), None);
+        assert_eq!(unescape_word("a\\"), None);
+    }
+
+    #[test]
+    fn negative_samples_need_two_words() {
+        let words = vec![("a".to_string(), 3), ("b".to_string(), 2)];
+        let dict = Dict::from_saved(words, Tokenizer::default());
+        let path = temp_path("single.noise");
+        fs::write(&path, "a\t1\nb\t0\n").unwrap();
+        let mut args = vocab_args();
+        args.noise_weights = Some(path.clone());
+        let single = dict.negative_sampler(&args);
+        fs::remove_file(&path).unwrap();
+        assert!(single.is_err());
+        args.noise_weights = None;
+        assert!(dict.negative_sampler(&args).is_ok());
+        let one = Dict::from_saved(vec![("a".to_string(), 3)], Tokenizer::default());
+        assert!(one.negative_sampler(&args).is_err());
+    }
+
+    #[test]
+    fn threads_count_like_one() {
+        let mut rng = crate::sampler::seeded_rng(3, 0);
+        let mut text = String::new();
+        for _ in 0..2000 {
+            for _ in 0..10 {
+                let bound = 1 + rng.gen_range(0, 300);
+                text += &format!("w{} ", rng.gen_range(0, bound));
+            }
+            text += "\n";
+        }
+        let path = temp_path("threads.txt");
+        fs::write(&path, text).unwrap();
+        let count = |threads: u32, max_vocab_size: Option<usize>| {
+            let args = ArgumentBuilder::new(path.clone(), Command::Vocab)
+                .output("vocab".to_string())
+                .min_count(1)
+                .threads(threads)
+                .max_vocab_size(max_vocab_size)
+                .build()
+                .unwrap();
+            let dict = Dict::new_from_file(&path, &args, &()).unwrap();
+            (dict.idx2word.clone(), dict.counts(), dict.ntokens)
+        };
+        let single = count(1, None);
+        let parallel = [count(4, None), count(4, Some(4 * 300))];
+        fs::remove_file(&path).unwrap();
+        assert_eq!(single.2, 20000);
+        for counts in parallel {
+            assert_eq!(counts, single);
+        }
+        // each thread keeps its share of the budget
+        let args = ArgumentBuilder::new(path, Command::Vocab)
+            .output("vocab".to_string())
+            .max_vocab_size(Some(1000))
+            .build()
+            .unwrap();
+        assert_eq!(WordCounts::new(&args, 4).max_size, Some(250));
+    }
+
+    #[test]
+    fn duplicate_words_are_rejected() {
+        let path = temp_path("duplicate.vocab");
+        fs::write(&path, format!("{}\t6\na\t3\nb\t2\na\t1\n", VOCAB_HEADER)).unwrap();
+        let loaded = Dict::load_vocab(&path, &vocab_args(), &());
+        fs::remove_file(&path).unwrap();
+        match loaded {
+            Err(W2vError::InvalidInput(message)) => assert!(message.contains("line 4"), "{}", message),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    // a 4, b 3, c 2, d 1 and e 1 times
+    const COUNTED: [&str; 11] = ["d", "a", "b", "c", "a", "e", "b", "a", "c", "b", "a"];
+
+    fn words(dict: &Dict) -> Vec<&str> {
+        dict.idx2word.iter().map(String::as_str).collect()
+    }
+
+    #[derive(Default)]
+    struct PrunedBelow(Mutex<Option<u32>>);
+    impl Observer for PrunedBelow {
+        fn on_event(&self, event: &Event) {
+            if let Event::VocabBuilt { pruned_below, .. } = *event {
+                *self.0.lock().unwrap() = pruned_below;
+            }
+        }
+    }
+
+    #[test]
+    fn pruning_raises_the_count_until_the_words_fit() {
+        let mut args = vocab_args();
+        args.max_vocab_size = Some(3);
+        let mut counts = WordCounts::new(&args, 1);
+        counts.add_tokens(&COUNTED, &mut 0);
+        assert_eq!(counts.min_reduce, 2);
+        assert_eq!(counts.words.len(), 3);
+
+        let pruned = PrunedBelow::default();
+        let dict = Dict::new_from_sentences([COUNTED], &args, &pruned);
+        assert_eq!(words(&dict), ["a", "b", "c"]);
+        assert_eq!(dict.counts(), [4, 3, 2]);
+        // the pruned words still count as tokens
+        assert_eq!(dict.ntokens, 11);
+        assert_eq!(*pruned.0.lock().unwrap(), Some(2));
+    }
+
+    #[test]
+    fn final_vocabulary_is_capped() {
+        let dict = |min_count: u32, max_final_vocab: Option<usize>, oov: Oov| {
+            let mut args = vocab_args();
+            args.min_count = min_count;
+            args.max_final_vocab = max_final_vocab;
+            args.oov = oov;
+            Dict::new_from_sentences([COUNTED], &args, &())
+        };
+        assert_eq!(words(&dict(1, Some(2), Oov::Error)), ["a", "b"]);
+        assert_eq!(words(&dict(3, Some(4), Oov::Error)), ["a", "b"]);
+        assert_eq!(words(&dict(1, Some(10), Oov::Error)).len(), 5);
+        // the words left out are counted as <unk>
+        let unk = dict(1, Some(2), Oov::Unk);
+        assert_eq!(words(&unk), [UNK_TOKEN, "a", "b"]);
+        assert_eq!(unk.counts(), [4, 4, 3]);
+        assert_eq!(unk.unk(), Some(0));
+    }
+
+    #[test]
+    fn indices_follow_descending_counts_then_words() {
+        let args = vocab_args();
+        let mut counts = WordCounts::new(&args, 1);
+        let records = [("b a c", "z"), ("d b a", "y"), ("c", "z")];
+        for (text, tag) in records {
+            let record = Record {
+                text: text.to_string(),
+                tag: Some(tag.to_string()),
+                weight: 1.0,
+            };
+            counts.add_record(&record, &mut 0);
+        }
+        let dict = Dict::from_counts(counts, &args, &());
+        // the tags go after all words
+        assert_eq!(words(&dict), ["a", "b", "c", "d", "z", "y"]);
+        assert_eq!(dict.counts(), [2, 2, 2, 1, 2, 1]);
+        assert_eq!(dict.nwords(), 4);
+        assert_eq!(dict.get_tag_idx("y"), Some(5));
+        // the order does not depend on the order of the words in the corpus
+        let shuffled = Dict::new_from_sentences([["d", "c", "b", "a", "c", "b", "a"]], &args, &());
+        assert_eq!(words(&shuffled), ["a", "b", "c", "d"]);
+    }
+}
@@ -0,0 +1,61 @@
+use crate::validation::Scores;
+
+// what training reports to the host program, instead of printing
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    // tokens counted so far, every million
+    WordsRead(usize),
+    VocabBuilt {
+        words: usize,
+        tokens: usize,
+        // records that could not be parsed in the input format
+        skipped: usize,
+        // words seen less than this were pruned while counting
+        pruned_below: Option<u32>,
+    },
+    TrainStarted {
+        seed: u64,
+        threads: u32,
+        // tokens of all epochs
+        tokens: usize,
+    },
+    // sent by the first thread every --lr_update of its tokens
+    Progress {
+        // part of the tokens of all epochs trained by all threads
+        progress: f32,
+        words: usize,
+        words_per_sec: f32,
+        lr: f32,
+        // mean loss of all threads since the previous Progress
+        loss: f64,
+    },
+    // sent when the first thread ends an epoch
+    EpochEnd {
+        epoch: u32,
+        // mean loss of all threads since the previous EpochEnd
+        loss: f64,
+    },
+    // the scores of an epoch on the held-out data, sent after its EpochEnd
+    Validated {
+        epoch: u32,
+        scores: Scores,
+        // the epoch with the best score so far, whose vectors are kept
+        best_epoch: u32,
+    },
+    // no improvement for --patience epochs
+    EarlyStopped {
+        epoch: u32,
+        best_epoch: u32,
+    },
+    Finished {
+        seconds: f64,
+    },
+}
+
+// receives the events of a training run, from any of its threads. the
+// default does nothing, which is what the library does without an observer
+pub trait Observer: Send + Sync {
+    fn on_event(&self, _event: &Event) {}
+}
+
+impl Observer for () {}
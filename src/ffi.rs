@@ -1,64 +1,438 @@
-use std::ffi::{CStr,CString};
+// the C API, declared in include/word2vec.h. models are opaque handles,
+// functions return a status and write a message to `error` when it is not
+// null, freed with w2v_string_free. panics are caught before they cross it
+use crate::utils::{parse_arguments, Command};
+use crate::{train, W2vError, Word2vec};
+use std::ffi::{CStr, CString};
 use std::os::raw::c_char;
-use crate::parse_arguments;
-use crate::train;
-use std::error::Error;
-// 0 : ok with ptr filled with argument 
-// -1: utf8 unicode parse error
-//-2 : 
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
 
+/// success
+pub const W2V_OK: i32 = 0;
+/// failure, described by the error message
+pub const W2V_ERROR: i32 = -1;
+/// the word has no vector
+pub const W2V_OUT_OF_VOCABULARY: i32 = -2;
+/// the word is skipped by the out of vocabulary policy of the model, nothing
+/// is written
+pub const W2V_SKIPPED: i32 = 1;
 
-#[no_mangle]
-pub extern "C" fn ffi_train(
-    args:*const *const c_char,n:i32,ptr: *mut *mut c_char
-    )->i32{
-    let mut v: Vec<String> = vec![];
-    for i in 0..n as isize{
-        unsafe{
-            let s = CStr::from_ptr(*(args.offset(i))).to_str();
-            match s {
-                Ok(arg) => v.push(arg.to_string()),
-                Err(e)=> {
-                    let reason = CString::from_vec_unchecked(
-                        e.description().as_bytes().to_vec()
-                        );
-                    *ptr = reason.into_raw();
-                    return -1;
-                },
+/// A trained or loaded model.
+pub struct W2vModel {
+    model: Word2vec,
+}
+
+// a copy of `s` the caller frees with w2v_string_free, nul bytes replaced
+fn c_string(s: &str) -> *mut c_char {
+    CString::new(s.replace('\0', " "))
+        .map(CString::into_raw)
+        .unwrap_or(ptr::null_mut())
+}
+
+unsafe fn set_error(error: *mut *mut c_char, message: &str) {
+    if !error.is_null() {
+        *error = c_string(message);
+    }
+}
+
+// runs `f`, turning its errors and panics into a status and a message
+unsafe fn guard<F>(error: *mut *mut c_char, f: F) -> i32
+where
+    F: FnOnce() -> Result<i32, W2vError>,
+{
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(status)) => status,
+        Ok(Err(err)) => {
+            set_error(error, &err.to_string());
+            match err {
+                W2vError::OutOfVocabulary(_) => W2V_OUT_OF_VOCABULARY,
+                _ => W2V_ERROR,
             }
         }
+        Err(panic) => {
+            let reason = panic
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            set_error(error, &format!("word2vec panicked: {}", reason));
+            W2V_ERROR
+        }
+    }
+}
+
+fn invalid(reason: &str) -> W2vError {
+    W2vError::InvalidInput(reason.to_string())
+}
+
+unsafe fn str_arg<'a>(s: *const c_char, name: &str) -> Result<&'a str, W2vError> {
+    if s.is_null() {
+        return Err(invalid(&format!("{} is null", name)));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| invalid(&format!("{} is not utf-8", name)))
+}
+
+unsafe fn model_arg<'a>(model: *const W2vModel) -> Result<&'a Word2vec, W2vError> {
+    model
+        .as_ref()
+        .map(|m| &m.model)
+        .ok_or_else(|| invalid("model is null"))
+}
+
+unsafe fn out_arg<'a, T>(out: *mut T, name: &str) -> Result<&'a mut T, W2vError> {
+    out.as_mut()
+        .ok_or_else(|| invalid(&format!("{} is null", name)))
+}
+
+// the command line in `args`, program name first
+unsafe fn train_args(args: *const *const c_char, n: i32) -> Result<crate::Argument, W2vError> {
+    if args.is_null() || n <= 0 {
+        return Err(invalid("no arguments"));
+    }
+    let mut v = Vec::new();
+    for i in 0..n as usize {
+        v.push(str_arg(*args.add(i), "argument")?.to_string());
+    }
+    let args = parse_arguments(&v).map_err(|err| invalid(&err.to_string()))?;
+    if args.command != Command::Train {
+        return Err(invalid("the arguments are not of the train command"));
     }
-    let args = parse_arguments(&v).unwrap();
-    let w2v = train(&args);
-    match w2v {
-        Ok(model) => {
-            match model.save_vectors(&args.output){
-                Ok(_) => {},
-                // save error
-                Err(e) => {
-                    unsafe{
-                        let reason = CString::from_vec_unchecked(
-                            e.description().as_bytes().to_vec()
-                            );
-                        *ptr = reason.into_raw();
-                    }
-                   return -1
-                }
+    Ok(args)
+}
+
+/// Trains with the `n` command line arguments `args`, the program name
+/// first, and saves the model to the output they name. Returns 0, or -1 with
+/// the reason in `ptr`.
+#[no_mangle]
+pub unsafe extern "C" fn ffi_train(
+    args: *const *const c_char,
+    n: i32,
+    ptr: *mut *mut c_char,
+) -> i32 {
+    guard(ptr, || {
+        let args = train_args(args, n)?;
+        train(&args)?.save_vectors(&args.output)?;
+        Ok(W2V_OK)
+    })
+}
+
+/// Trains with the `n` command line arguments `args`, the program name
+/// first, into a new model in `model`, without saving it.
+#[no_mangle]
+pub unsafe extern "C" fn w2v_train(
+    args: *const *const c_char,
+    n: i32,
+    model: *mut *mut W2vModel,
+    error: *mut *mut c_char,
+) -> i32 {
+    guard(error, || {
+        let out = out_arg(model, "model")?;
+        let args = train_args(args, n)?;
+        let trained = train(&args)?;
+        *out = Box::into_raw(Box::new(W2vModel { model: trained }));
+        Ok(W2V_OK)
+    })
+}
+
+/// Reads a model saved by w2v_save or the command line into `model`.
+#[no_mangle]
+pub unsafe extern "C" fn w2v_load(
+    path: *const c_char,
+    model: *mut *mut W2vModel,
+    error: *mut *mut c_char,
+) -> i32 {
+    guard(error, || {
+        let out = out_arg(model, "model")?;
+        let loaded = Word2vec::load(str_arg(path, "path")?)?;
+        *out = Box::into_raw(Box::new(W2vModel { model: loaded }));
+        Ok(W2V_OK)
+    })
+}
+
+/// Writes the vocabulary to `path`, the vectors to `path`.vec and the
+/// tokenizer to `path`.tokenizer.
+#[no_mangle]
+pub unsafe extern "C" fn w2v_save(
+    model: *const W2vModel,
+    path: *const c_char,
+    error: *mut *mut c_char,
+) -> i32 {
+    guard(error, || {
+        model_arg(model)?.save_vectors(str_arg(path, "path")?)?;
+        Ok(W2V_OK)
+    })
+}
+
+/// Frees a model of w2v_train or w2v_load, null is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn w2v_free(model: *mut W2vModel) {
+    if !model.is_null() {
+        // dropping only frees memory, nothing to unwind from
+        drop(Box::from_raw(model));
+    }
+}
+
+/// Frees a string returned by the API, null is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn w2v_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// The size of the vectors, 0 for a null model.
+#[no_mangle]
+pub unsafe extern "C" fn w2v_dim(model: *const W2vModel) -> usize {
+    model_arg(model).map_or(0, |m| m.dim())
+}
+
+/// The number of words, 0 for a null model.
+#[no_mangle]
+pub unsafe extern "C" fn w2v_vocab_size(model: *const W2vModel) -> usize {
+    model_arg(model).map_or(0, |m| m.vocab_size())
+}
+
+/// The index of `word`, below w2v_vocab_size, or -1 when it is not in the
+/// vocabulary.
+#[no_mangle]
+pub unsafe extern "C" fn w2v_index(model: *const W2vModel, word: *const c_char) -> i64 {
+    let index = || -> Option<usize> {
+        model_arg(model)
+            .ok()?
+            .get_index(str_arg(word, "word").ok()?)
+    };
+    catch_unwind(AssertUnwindSafe(index))
+        .ok()
+        .flatten()
+        .map_or(-1, |i| i as i64)
+}
+
+/// Writes the word at `index` to `word`, a string freed with
+/// w2v_string_free. Iterates the vocabulary with indices below
+/// w2v_vocab_size.
+#[no_mangle]
+pub unsafe extern "C" fn w2v_word(
+    model: *const W2vModel,
+    index: usize,
+    word: *mut *mut c_char,
+    error: *mut *mut c_char,
+) -> i32 {
+    guard(error, || {
+        let out = out_arg(word, "word")?;
+        let found = model_arg(model)?
+            .get_word(index)
+            .ok_or_else(|| invalid(&format!("index {} is out of the vocabulary", index)))?;
+        *out = c_string(found);
+        Ok(W2V_OK)
+    })
+}
+
+/// Copies the vector of `word` to `vector`, which holds `len` floats, at
+/// least w2v_dim. Words out of the vocabulary follow the policy of the
+/// model: W2V_OUT_OF_VOCABULARY, W2V_SKIPPED, zeros or the unknown word
+/// vector.
+#[no_mangle]
+pub unsafe extern "C" fn w2v_vector(
+    model: *const W2vModel,
+    word: *const c_char,
+    vector: *mut f32,
+    len: usize,
+    error: *mut *mut c_char,
+) -> i32 {
+    guard(error, || {
+        let model = model_arg(model)?;
+        if vector.is_null() || len < model.dim() {
+            return Err(invalid(&format!("vector must hold {} floats", model.dim())));
+        }
+        match model.lookup(str_arg(word, "word")?)? {
+            Some(found) => {
+                slice::from_raw_parts_mut(vector, model.dim()).copy_from_slice(&found);
+                Ok(W2V_OK)
             }
-             
-        },
-        // train error
-        Err(e) => {
-            unsafe{
-             let reason = CString::from_vec_unchecked(
-                        format!("{}",e).as_bytes().to_vec()
-                        );
-                    *ptr = reason.into_raw();
+            None => Ok(W2V_SKIPPED),
+        }
+    })
+}
+
+/// Writes the cosine similarity of `a` and `b` to `similarity`, W2V_SKIPPED
+/// when the model skips one of them.
+#[no_mangle]
+pub unsafe extern "C" fn w2v_similarity(
+    model: *const W2vModel,
+    a: *const c_char,
+    b: *const c_char,
+    similarity: *mut f32,
+    error: *mut *mut c_char,
+) -> i32 {
+    guard(error, || {
+        let out = out_arg(similarity, "similarity")?;
+        let (a, b) = (str_arg(a, "a")?, str_arg(b, "b")?);
+        match model_arg(model)?.similarity(a, b)? {
+            Some(s) => {
+                *out = s;
+                Ok(W2V_OK)
             }
-            return -1;
+            None => Ok(W2V_SKIPPED),
+        }
+    })
+}
 
-        },
-    }  
-    // no error
-    0
+/// Writes the `k` words closest to `word` by cosine similarity, the closest
+/// first, to `words` and their similarities to `scores`, both arrays of `k`
+/// items, and how many there are to `count`. Each word is freed with
+/// w2v_string_free.
+#[no_mangle]
+pub unsafe extern "C" fn w2v_most_similar(
+    model: *const W2vModel,
+    word: *const c_char,
+    k: usize,
+    words: *mut *mut c_char,
+    scores: *mut f32,
+    count: *mut usize,
+    error: *mut *mut c_char,
+) -> i32 {
+    guard(error, || {
+        let count = out_arg(count, "count")?;
+        if k > 0 && (words.is_null() || scores.is_null()) {
+            return Err(invalid("words and scores are null"));
+        }
+        let similar = model_arg(model)?.most_similar(str_arg(word, "word")?, Some(k))?;
+        for (i, (score, similar)) in similar.iter().enumerate() {
+            *words.add(i) = c_string(similar);
+            *scores.add(i) = *score;
+        }
+        *count = similar.len();
+        Ok(W2V_OK)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dictionary::Oov;
+    use crate::{Dict, Matrix, Tokenizer};
+
+    // words x and y along the axes of the plane
+    fn model() -> W2vModel {
+        let counts = vec![("x".to_string(), 2), ("y".to_string(), 1)];
+        let dict = Dict::from_saved(counts, Tokenizer::default());
+        let vectors = Matrix::from_vec(vec![1., 0., 0., 1.], 2);
+        W2vModel {
+            model: Word2vec::new(vectors, Matrix::new(0, 2), 2, dict),
+        }
+    }
+
+    // the message of a failed call, freed
+    unsafe fn message(error: *mut c_char) -> String {
+        assert!(!error.is_null());
+        let message = CStr::from_ptr(error).to_str().unwrap().to_string();
+        w2v_string_free(error);
+        message
+    }
+
+    unsafe fn vector(model: &W2vModel, word: &CStr) -> (i32, [f32; 2], *mut c_char) {
+        let (mut vector, mut error) = ([9f32; 2], ptr::null_mut());
+        let status = w2v_vector(model, word.as_ptr(), vector.as_mut_ptr(), 2, &mut error);
+        (status, vector, error)
+    }
+
+    #[test]
+    fn vectors_follow_the_oov_policy() {
+        let mut model = model();
+        unsafe {
+            let (status, found, error) = vector(&model, c"y");
+            assert_eq!((status, found, error), (W2V_OK, [0., 1.], ptr::null_mut()));
+            let (status, _, error) = vector(&model, c"z");
+            assert_eq!(status, W2V_OUT_OF_VOCABULARY);
+            assert!(message(error).contains('z'));
+            model.model.set_oov(Oov::Skip);
+            let (status, untouched, error) = vector(&model, c"z");
+            assert_eq!(
+                (status, untouched, error),
+                (W2V_SKIPPED, [9., 9.], ptr::null_mut())
+            );
+            model.model.set_oov(Oov::Zero);
+            assert_eq!(vector(&model, c"z").1, [0., 0.]);
+        }
+    }
+
+    #[test]
+    fn null_and_short_arguments_are_errors() {
+        let model = model();
+        let mut error = ptr::null_mut();
+        let mut out = [0f32; 2];
+        unsafe {
+            let status = w2v_vector(ptr::null(), c"x".as_ptr(), out.as_mut_ptr(), 2, &mut error);
+            assert_eq!(status, W2V_ERROR);
+            assert!(message(error).contains("model is null"));
+            let status = w2v_vector(&model, ptr::null(), out.as_mut_ptr(), 2, &mut error);
+            assert_eq!(status, W2V_ERROR);
+            assert!(message(error).contains("word is null"));
+            let status = w2v_vector(&model, c"x".as_ptr(), out.as_mut_ptr(), 1, &mut error);
+            assert_eq!(status, W2V_ERROR);
+            assert!(message(error).contains("2 floats"));
+            // without a place for the message only the status tells
+            let status = w2v_vector(&model, c"x".as_ptr(), ptr::null_mut(), 2, ptr::null_mut());
+            assert_eq!(status, W2V_ERROR);
+            let status = w2v_word(&model, 2, &mut ptr::null_mut(), &mut error);
+            assert_eq!(status, W2V_ERROR);
+            assert!(message(error).contains("index 2"));
+
+            assert_eq!(w2v_dim(ptr::null()), 0);
+            assert_eq!(w2v_vocab_size(ptr::null()), 0);
+            assert_eq!(w2v_index(ptr::null(), c"x".as_ptr()), -1);
+            assert_eq!(w2v_index(&model, c"z".as_ptr()), -1);
+            assert_eq!(w2v_index(&model, c"y".as_ptr()), 1);
+            w2v_free(ptr::null_mut());
+            w2v_string_free(ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn training_needs_train_arguments() {
+        let mut model = ptr::null_mut();
+        let mut error = ptr::null_mut();
+        unsafe {
+            assert_eq!(w2v_train(ptr::null(), 0, &mut model, &mut error), W2V_ERROR);
+            assert!(message(error).contains("no arguments"));
+            let args = [
+                c"word2vec".as_ptr(),
+                c"vocab".as_ptr(),
+                c"in".as_ptr(),
+                c"out".as_ptr(),
+            ];
+            assert_eq!(
+                w2v_train(args.as_ptr(), 4, &mut model, &mut error),
+                W2V_ERROR
+            );
+            assert!(message(error).contains("train command"));
+            let args = [c"word2vec".as_ptr(), c"train".as_ptr(), c"--dim".as_ptr()];
+            assert_eq!(ffi_train(args.as_ptr(), 3, &mut error), W2V_ERROR);
+            message(error);
+        }
+        assert!(model.is_null());
+    }
+
+    #[test]
+    fn panics_become_errors() {
+        let mut error = ptr::null_mut();
+        unsafe {
+            let status = guard(&mut error, || panic!("at the disco"));
+            assert_eq!(status, W2V_ERROR);
+            assert_eq!(message(error), "word2vec panicked: at the disco");
+            let status = guard(&mut error, || {
+                Err(W2vError::OutOfVocabulary("z".to_string()))
+            });
+            assert_eq!(status, W2V_OUT_OF_VOCABULARY);
+            message(error);
+            // a success leaves the message alone
+            error = ptr::null_mut();
+            assert_eq!(guard(&mut error, || Ok(W2V_SKIPPED)), W2V_SKIPPED);
+        }
+        assert!(error.is_null());
+    }
 }
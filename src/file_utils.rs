@@ -1,3 +1,4 @@
+use crate::W2vError;
 use parquet::{
     arrow::arrow_reader,
     errors::ParquetError,
@@ -53,36 +54,51 @@ impl LineReader<StringResult, Lines<BufReader<File>>> for FileBufLineReader {
     }
 }
 
-pub struct TakeBufStrReader {
-    pub handle: Take<BufReader<File>>,
-    pub buf: String
-}
-impl TakeBufStrReader {
-    pub fn next_line<'a>(&mut self, buf: &'a mut String) -> Result<&'a mut String, IOError> {
-        let res = self.handle.read_line(buf);
-        res.map(|b| buf)
-    }
-
-    //fn from_file<'a>(file: File) -> FileBufLineReader {
-    //    let reader = FileBufLineReader { lines: BufReader::new(file).lines() };
-    //    reader
-    //}
-    pub fn from_handle(handle: Take<BufReader<File>>) -> Self {
-        let buf = String::new();
-        TakeBufStrReader { handle: handle, buf: buf }
+// byte offsets splitting `filename` into `n_split` parts at line ends
+pub fn file_split_indices(filename: &str, n_split: u64) -> Result<Vec<u64>, W2vError> {
+    let all_tokens = metadata(filename)?.len();
+    let input_file = File::open(filename)?;
+    let mut reader = BufReader::with_capacity(1000, input_file);
+    let offset = all_tokens / n_split;
+    let mut junk = Vec::new();
+    let mut bytes = Vec::new();
+    bytes.push(0);
+    for i in 1..n_split {
+        reader.seek(SeekFrom::Start(offset * i)).unwrap();
+        let extra = reader.read_until(b'\n', &mut junk)?;
+        bytes.push(offset * i + extra as u64);
     }
+    bytes.push(all_tokens);
+    Ok(bytes)
 }
 
+pub fn get_file_range_reader(
+    filename: &str,
+    start_pos: u64,
+    end_pos: u64,
+) -> Result<Take<BufReader<File>>, std::io::Error> {
+    let input_file = File::open(filename)?;
+    let mut reader = BufReader::with_capacity(10000, input_file);
+    reader.seek(SeekFrom::Start(start_pos))?;
+    Ok(reader.take(end_pos - start_pos))
+}
 
-impl Iterator for TakeBufStrReader {
-    type Item = String;
-    fn next(&mut self) -> Option<Self::Item> {
-        let res = self.handle.read_line(&mut self.buf);
-        match res {
-            Ok(0) => None,
-            Ok(_) => Some(self.buf.clone()),
-            _ => None
-        }
+// copies everything read from `inner` into `copy`, so a stream that can only
+// be consumed once (e.g. stdin) can be replayed from the copy afterwards
+pub struct TeeReader<R: Read, W: Write> {
+    inner: R,
+    copy: W,
+}
+impl<R: Read, W: Write> TeeReader<R, W> {
+    pub fn new(inner: R, copy: W) -> Self {
+        TeeReader { inner, copy }
+    }
+}
+impl<R: Read, W: Write> Read for TeeReader<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IOError> {
+        let n = self.inner.read(buf)?;
+        self.copy.write_all(&buf[..n])?;
+        Ok(n)
     }
 }
 
@@ -0,0 +1,722 @@
+// hierarchical navigable small world graph over the word vectors, for
+// approximate nearest neighbours by cosine similarity (Malkov & Yashunin).
+// every word is a node of the bottom layer with up to 2 * m links, and of
+// the layers above up to its random level with up to m links each. queries
+// descend greedily from the top and search the bottom layer with a list of
+// ef candidates
+use crate::sampler::seeded_rng;
+use crate::{dot_product, Matrix, W2vError};
+use rand::distributions::{IndependentSample, Range};
+use rand::Rng;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::mem::size_of;
+use std::slice;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::Mutex;
+use std::thread;
+use time::Instant;
+
+// native byte order: MAGIC, the HEADER_FIELDS u64 of the header, then the
+// links of the bottom layer, the start of each word in the upper layers and
+// the links of the upper layers, all u32
+const MAGIC: &[u8; 8] = b"W2VHNSW2";
+const HEADER_FIELDS: usize = 9;
+// an unused link slot
+const NONE: u32 = u32::MAX;
+// the neighbours checked against exact search after building
+const RECALL_K: usize = 10;
+// more links per word than this are refused, when building and loading
+pub(crate) const MAX_M: usize = 1 << 12;
+
+/// Parameters of the index.
+#[derive(Debug, Clone, Copy)]
+pub struct HnswParams {
+    /// links per word in the upper layers, twice that in the bottom one(16)
+    pub m: usize,
+    /// candidates considered when linking a word(200)
+    pub ef_construction: usize,
+    /// candidates considered by queries, at least the words asked for(64)
+    pub ef_search: usize,
+    /// words whose neighbours are compared with exact search after
+    /// building(1000)
+    pub recall_queries: usize,
+    /// seed of the levels of the words and of the words checked(0)
+    pub seed: u64,
+}
+
+impl Default for HnswParams {
+    fn default() -> HnswParams {
+        HnswParams {
+            m: 16,
+            ef_construction: 200,
+            ef_search: 64,
+            recall_queries: 1000,
+            seed: 0,
+        }
+    }
+}
+
+impl HnswParams {
+    /// the parameters, or the first invalid one
+    pub fn validate(&self) -> Result<(), W2vError> {
+        let invalid = |name: &str, reason: &str| {
+            Err(W2vError::InvalidInput(format!(
+                "invalid {}: {}",
+                name, reason
+            )))
+        };
+        if self.m < 2 || self.m > MAX_M {
+            return invalid("m", &format!("must be between 2 and {}", MAX_M));
+        }
+        if self.ef_construction == 0 {
+            return invalid("ef_construction", "must be at least 1");
+        }
+        if self.ef_search == 0 {
+            return invalid("ef_search", "must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+/// How building went.
+#[derive(Debug, Clone, Copy)]
+pub struct IndexReport {
+    /// part of the exact 10 nearest neighbours of the checked words the
+    /// index finds
+    pub recall: f64,
+    /// words checked
+    pub queries: usize,
+    pub seconds: f64,
+}
+
+// a node and its distance to the query, the closest first when sorted
+#[derive(Clone, Copy, PartialEq)]
+struct Near(f32, u32);
+impl Eq for Near {}
+impl PartialOrd for Near {
+    fn partial_cmp(&self, other: &Near) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Near {
+    fn cmp(&self, other: &Near) -> Ordering {
+        self.0.total_cmp(&other.0).then(self.1.cmp(&other.1))
+    }
+}
+
+// the links of a graph, while building or built
+trait Graph {
+    fn neighbours(&self, node: u32, layer: usize, out: &mut Vec<u32>);
+}
+
+// the rows of the vectors with their inverse norms
+struct Vectors<'a> {
+    matrix: &'a Matrix,
+    inv_norms: &'a [f32],
+}
+
+impl Vectors<'_> {
+    // cosine distance of two words
+    fn distance(&self, a: u32, b: u32) -> f32 {
+        let (ra, rb) = (self.matrix.row(a as usize), self.matrix.row(b as usize));
+        let dot = unsafe { dot_product(ra.as_ptr(), rb.as_ptr(), ra.len()) };
+        1. - dot * self.inv_norms[a as usize] * self.inv_norms[b as usize]
+    }
+    // cosine distance of a word to a query of inverse norm `inv_query`
+    fn query_distance(&self, query: &[f32], inv_query: f32, b: u32) -> f32 {
+        let rb = self.matrix.row(b as usize);
+        let dot = unsafe { dot_product(query.as_ptr(), rb.as_ptr(), rb.len()) };
+        1. - dot * inv_query * self.inv_norms[b as usize]
+    }
+}
+
+fn inverse_norm(v: &[f32]) -> f32 {
+    let norm = unsafe { dot_product(v.as_ptr(), v.as_ptr(), v.len()) }.sqrt();
+    if norm > 0. {
+        1. / norm
+    } else {
+        0.
+    }
+}
+
+// fnv-1a of the shape and the values of the vectors, to tell an index of
+// other vectors of the same vocabulary, like those of an earlier training
+fn fingerprint(matrix: &Matrix) -> u64 {
+    let shape = [matrix.rows() as u32, matrix.row_size() as u32];
+    let values = matrix.as_slice().iter().map(|x| x.to_bits());
+    shape
+        .into_iter()
+        .chain(values)
+        .fold(0xcbf29ce484222325, |hash, bits| {
+            (hash ^ bits as u64).wrapping_mul(0x100000001b3)
+        })
+}
+
+fn inverse_norms(matrix: &Matrix) -> Vec<f32> {
+    (0..matrix.rows())
+        .map(|i| inverse_norm(matrix.row(i)))
+        .collect()
+}
+
+// the closest node to the query found from `entry` on `layer`, following
+// links while they get closer
+fn greedy<G: Graph, D: Fn(u32) -> f32>(graph: &G, distance: &D, entry: Near, layer: usize) -> Near {
+    let mut best = entry;
+    let mut links = Vec::new();
+    loop {
+        let current = best;
+        links.clear();
+        graph.neighbours(current.1, layer, &mut links);
+        for &node in &links {
+            let d = distance(node);
+            if d < best.0 {
+                best = Near(d, node);
+            }
+        }
+        if best == current {
+            return best;
+        }
+    }
+}
+
+// the `ef` closest nodes to the query found from `entry` on `layer`, the
+// closest first
+fn search_layer<G: Graph, D: Fn(u32) -> f32>(
+    graph: &G,
+    distance: &D,
+    entry: Near,
+    ef: usize,
+    layer: usize,
+) -> Vec<Near> {
+    let mut visited = HashSet::new();
+    visited.insert(entry.1);
+    let mut candidates = BinaryHeap::new();
+    candidates.push(Reverse(entry));
+    // the farthest of the results on top
+    let mut results = BinaryHeap::new();
+    results.push(entry);
+    let mut links = Vec::new();
+    while let Some(Reverse(closest)) = candidates.pop() {
+        if results.len() >= ef && closest.0 > results.peek().map_or(f32::MAX, |n: &Near| n.0) {
+            break;
+        }
+        links.clear();
+        graph.neighbours(closest.1, layer, &mut links);
+        for &node in &links {
+            if !visited.insert(node) {
+                continue;
+            }
+            let d = distance(node);
+            if results.len() < ef || d < results.peek().map_or(f32::MAX, |n| n.0) {
+                candidates.push(Reverse(Near(d, node)));
+                results.push(Near(d, node));
+                if results.len() > ef {
+                    results.pop();
+                }
+            }
+        }
+    }
+    results.into_sorted_vec()
+}
+
+// up to `m` of the candidates, closest first, skipping those closer to an
+// already chosen one than to the node so that the links spread out, then
+// filling up with the skipped ones
+fn select(vectors: &Vectors, candidates: &[Near], m: usize) -> Vec<u32> {
+    let mut chosen: Vec<u32> = Vec::with_capacity(m);
+    let mut skipped = Vec::new();
+    for &Near(d, node) in candidates {
+        if chosen.len() == m {
+            break;
+        }
+        if chosen.iter().all(|&c| vectors.distance(c, node) > d) {
+            chosen.push(node);
+        } else {
+            skipped.push(node);
+        }
+    }
+    let missing = m - chosen.len();
+    chosen.extend(skipped.into_iter().take(missing));
+    chosen
+}
+
+// the graph while words are inserted concurrently, each link list locked
+// on its own
+struct Builder<'a> {
+    vectors: Vectors<'a>,
+    params: HnswParams,
+    levels: Vec<usize>,
+    // per word, its link lists from the bottom layer up
+    links: Vec<Vec<Mutex<Vec<u32>>>>,
+    // entry node and top layer
+    entry: Mutex<(u32, usize)>,
+}
+
+impl Graph for Builder<'_> {
+    fn neighbours(&self, node: u32, layer: usize, out: &mut Vec<u32>) {
+        if let Some(links) = self.links[node as usize].get(layer) {
+            out.extend_from_slice(&links.lock().unwrap());
+        }
+    }
+}
+
+impl Builder<'_> {
+    fn max_links(&self, layer: usize) -> usize {
+        if layer == 0 {
+            2 * self.params.m
+        } else {
+            self.params.m
+        }
+    }
+
+    fn insert(&self, node: u32) {
+        let level = self.levels[node as usize];
+        let (entry, top) = *self.entry.lock().unwrap();
+        let distance = |other: u32| self.vectors.distance(node, other);
+        let mut nearest = Near(distance(entry), entry);
+        for layer in (level + 1..=top).rev() {
+            nearest = greedy(self, &distance, nearest, layer);
+        }
+        for layer in (0..=level.min(top)).rev() {
+            let mut candidates =
+                search_layer(self, &distance, nearest, self.params.ef_construction, layer);
+            candidates.retain(|near| near.1 != node);
+            if candidates.is_empty() {
+                continue;
+            }
+            let chosen = select(&self.vectors, &candidates, self.max_links(layer));
+            *self.links[node as usize][layer].lock().unwrap() = chosen.clone();
+            for &other in &chosen {
+                self.link(other, node, layer);
+            }
+            nearest = candidates[0];
+        }
+        if level > top {
+            let mut entry = self.entry.lock().unwrap();
+            if level > entry.1 {
+                *entry = (node, level);
+            }
+        }
+    }
+
+    // adds `node` to the links of `to`, selecting again when they are full
+    fn link(&self, to: u32, node: u32, layer: usize) {
+        let mut links = self.links[to as usize][layer].lock().unwrap();
+        if links.contains(&node) {
+            return;
+        }
+        links.push(node);
+        let m = self.max_links(layer);
+        if links.len() > m {
+            let mut candidates: Vec<Near> = links
+                .iter()
+                .map(|&other| Near(self.vectors.distance(to, other), other))
+                .collect();
+            candidates.sort_unstable();
+            *links = select(&self.vectors, &candidates, m);
+        }
+    }
+}
+
+/// The index of the vectors of a model, see `Word2vec::build_index`.
+#[derive(Debug)]
+pub struct Hnsw {
+    m: usize,
+    ef_construction: usize,
+    ef_search: usize,
+    entry: u32,
+    top: usize,
+    // 2 * m slots per word, NONE for the unused ones
+    bottom: Vec<u32>,
+    // per word the start of its layers above the bottom in `upper`, m slots
+    // per layer, NONE for a word only in the bottom layer
+    upper_start: Vec<u32>,
+    upper: Vec<u32>,
+    inv_norms: Vec<f32>,
+    // of the vectors it links
+    dim: usize,
+    fingerprint: u64,
+}
+
+impl Graph for Hnsw {
+    fn neighbours(&self, node: u32, layer: usize, out: &mut Vec<u32>) {
+        let links = if layer == 0 {
+            let m0 = 2 * self.m;
+            self.bottom
+                .get(node as usize * m0..(node as usize + 1) * m0)
+        } else {
+            let start = self.upper_start[node as usize];
+            if start == NONE {
+                return;
+            }
+            let start = start as usize + (layer - 1) * self.m;
+            self.upper.get(start..start + self.m)
+        };
+        out.extend(links.unwrap_or(&[]).iter().take_while(|&&n| n != NONE));
+    }
+}
+
+fn write_u32s<W: Write>(writer: &mut W, values: &[u32]) -> Result<(), W2vError> {
+    let bytes = unsafe {
+        slice::from_raw_parts(values.as_ptr() as *const u8, std::mem::size_of_val(values))
+    };
+    writer.write_all(bytes)?;
+    Ok(())
+}
+
+fn read_u32s<R: Read>(reader: &mut R, len: usize) -> Result<Vec<u32>, W2vError> {
+    let mut values = vec![0u32; len];
+    let bytes = unsafe {
+        slice::from_raw_parts_mut(values.as_mut_ptr() as *mut u8, len * size_of::<u32>())
+    };
+    reader.read_exact(bytes)?;
+    Ok(values)
+}
+
+impl Hnsw {
+    // links every row of `matrix` on `threads` threads, in the order of the
+    // vocabulary
+    pub fn build(matrix: &Matrix, params: HnswParams, threads: u32) -> Hnsw {
+        let n = matrix.rows();
+        let inv_norms = inverse_norms(matrix);
+        let mut rng = seeded_rng(params.seed, 0);
+        let unit = Range::new(f64::MIN_POSITIVE, 1.);
+        let scale = 1. / (params.m as f64).ln();
+        let levels: Vec<usize> = (0..n)
+            .map(|_| (-unit.ind_sample(&mut rng).ln() * scale) as usize)
+            .collect();
+        let links = levels
+            .iter()
+            .map(|&level| (0..=level).map(|_| Mutex::new(Vec::new())).collect())
+            .collect();
+        let builder = Builder {
+            vectors: Vectors {
+                matrix,
+                inv_norms: &inv_norms,
+            },
+            params,
+            entry: Mutex::new((0, levels.first().copied().unwrap_or(0))),
+            levels,
+            links,
+        };
+        let next = AtomicUsize::new(1);
+        thread::scope(|s| {
+            for _ in 0..threads.max(1) {
+                s.spawn(|| loop {
+                    let node = next.fetch_add(1, AtomicOrdering::Relaxed);
+                    if node >= n {
+                        break;
+                    }
+                    builder.insert(node as u32);
+                });
+            }
+        });
+        let (entry, top) = builder.entry.into_inner().unwrap();
+        let m0 = 2 * params.m;
+        let mut bottom = vec![NONE; n * m0];
+        let mut upper_start = vec![NONE; n];
+        let mut upper = Vec::new();
+        for (node, layers) in builder.links.into_iter().enumerate() {
+            for (layer, links) in layers.into_iter().enumerate() {
+                let links = links.into_inner().unwrap();
+                if layer == 0 {
+                    bottom[node * m0..node * m0 + links.len()].copy_from_slice(&links);
+                    continue;
+                }
+                if layer == 1 {
+                    upper_start[node] = upper.len() as u32;
+                }
+                let start = upper.len();
+                upper.resize(start + params.m, NONE);
+                upper[start..start + links.len()].copy_from_slice(&links);
+            }
+        }
+        Hnsw {
+            m: params.m,
+            ef_construction: params.ef_construction,
+            ef_search: params.ef_search,
+            entry,
+            top,
+            bottom,
+            upper_start,
+            upper,
+            inv_norms,
+            dim: matrix.row_size(),
+            fingerprint: fingerprint(matrix),
+        }
+    }
+
+    pub fn set_ef_search(&mut self, ef_search: usize) {
+        self.ef_search = ef_search.max(1);
+    }
+
+    // the `k` rows closest to `query` by cosine similarity, the closest
+    // first, with their similarity
+    pub fn search(&self, matrix: &Matrix, query: &[f32], k: usize) -> Vec<(f32, usize)> {
+        if self.inv_norms.is_empty() || k == 0 {
+            return Vec::new();
+        }
+        let vectors = Vectors {
+            matrix,
+            inv_norms: &self.inv_norms,
+        };
+        let inv_query = inverse_norm(query);
+        let distance = |node: u32| vectors.query_distance(query, inv_query, node);
+        let mut nearest = Near(distance(self.entry), self.entry);
+        for layer in (1..=self.top).rev() {
+            nearest = greedy(self, &distance, nearest, layer);
+        }
+        search_layer(self, &distance, nearest, self.ef_search.max(k), 0)
+            .into_iter()
+            .take(k)
+            .map(|Near(d, node)| (1. - d, node as usize))
+            .collect()
+    }
+
+    // the part of the exact RECALL_K nearest neighbours of some rows the
+    // index finds, the rows themselves left out
+    pub fn recall(&self, matrix: &Matrix, queries: usize, seed: u64, threads: u32) -> (f64, usize) {
+        let n = matrix.rows();
+        let queries = queries.min(n);
+        if queries == 0 || n < 2 {
+            return (1., 0);
+        }
+        let mut rng = seeded_rng(seed, 1);
+        let sample: Vec<usize> = (0..queries).map(|_| rng.gen_range(0, n)).collect();
+        let vectors = Vectors {
+            matrix,
+            inv_norms: &self.inv_norms,
+        };
+        let found = AtomicUsize::new(0);
+        let chunk = sample.len().div_ceil(threads.max(1) as usize);
+        thread::scope(|s| {
+            for part in sample.chunks(chunk) {
+                let (vectors, found) = (&vectors, &found);
+                s.spawn(move || {
+                    for &row in part {
+                        let mut exact: Vec<Near> = (0..n as u32)
+                            .filter(|&other| other as usize != row)
+                            .map(|other| Near(vectors.distance(row as u32, other), other))
+                            .collect();
+                        let k = RECALL_K.min(exact.len());
+                        exact.select_nth_unstable(k - 1);
+                        exact.truncate(k);
+                        let approximate: Vec<usize> = self
+                            .search(matrix, matrix.row(row), k + 1)
+                            .into_iter()
+                            .map(|(_, node)| node)
+                            .filter(|&node| node != row)
+                            .collect();
+                        let hits = exact
+                            .iter()
+                            .filter(|near| {
+                                approximate
+                                    .iter()
+                                    .take(k)
+                                    .any(|&node| node == near.1 as usize)
+                            })
+                            .count();
+                        found.fetch_add(hits, AtomicOrdering::Relaxed);
+                    }
+                });
+            }
+        });
+        let total = sample.len() * RECALL_K.min(n - 1);
+        (found.into_inner() as f64 / total as f64, sample.len())
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), W2vError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        let header: [u64; HEADER_FIELDS] = [
+            self.inv_norms.len() as u64,
+            self.dim as u64,
+            self.fingerprint,
+            self.m as u64,
+            self.ef_construction as u64,
+            self.ef_search as u64,
+            self.entry as u64,
+            self.top as u64,
+            self.upper.len() as u64,
+        ];
+        for field in header {
+            writer.write_all(&field.to_ne_bytes())?;
+        }
+        write_u32s(&mut writer, &self.bottom)?;
+        write_u32s(&mut writer, &self.upper_start)?;
+        write_u32s(&mut writer, &self.upper)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    // the index saved for the rows of `matrix`
+    pub fn load(path: &str, matrix: &Matrix) -> Result<Hnsw, W2vError> {
+        let invalid = || W2vError::InvalidInput(format!("{} is not an index of the model", path));
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid());
+        }
+        let mut header = [0u64; HEADER_FIELDS];
+        for field in header.iter_mut() {
+            let mut bytes = [0u8; 8];
+            reader.read_exact(&mut bytes)?;
+            *field = u64::from_ne_bytes(bytes);
+        }
+        let hash = header[2];
+        let [n, dim, _, m, ef_construction, ef_search, entry, top, upper_len] =
+            header.map(|f| f as usize);
+        if n != matrix.rows() || dim != matrix.row_size() || hash != fingerprint(matrix) {
+            return Err(W2vError::InvalidInput(format!(
+                "{} was built from other vectors than those of the model, build it again",
+                path
+            )));
+        }
+        // the entry word is in every layer above the bottom
+        if m == 0 || m > MAX_M || (n > 0 && entry >= n) || upper_len % m != 0 || top > upper_len / m
+        {
+            return Err(invalid());
+        }
+        // the links the header counts are all there is left
+        let links = (n as u64)
+            .checked_mul(2 * m as u64 + 1)
+            .and_then(|l| l.checked_add(upper_len as u64))
+            .and_then(|l| l.checked_mul(size_of::<u32>() as u64));
+        if links != Some(len - (MAGIC.len() + HEADER_FIELDS * 8) as u64) {
+            return Err(invalid());
+        }
+        let bottom = read_u32s(&mut reader, n * 2 * m)?;
+        let upper_start = read_u32s(&mut reader, n)?;
+        let upper = read_u32s(&mut reader, upper_len)?;
+        let in_range = |&id: &u32| id == NONE || (id as usize) < n;
+        if !bottom.iter().all(in_range) || !upper.iter().all(in_range) {
+            return Err(invalid());
+        }
+        Ok(Hnsw {
+            m,
+            ef_construction,
+            ef_search,
+            entry: entry as u32,
+            top,
+            bottom,
+            upper_start,
+            upper,
+            inv_norms: inverse_norms(matrix),
+            dim,
+            fingerprint: hash,
+        })
+    }
+}
+
+// builds the index of `matrix` and checks it against exact search
+pub fn build_checked(
+    matrix: &Matrix,
+    params: HnswParams,
+    threads: u32,
+) -> Result<(Hnsw, IndexReport), W2vError> {
+    params.validate()?;
+    let start = Instant::now();
+    let index = Hnsw::build(matrix, params, threads);
+    let seconds = start.elapsed().as_seconds_f64();
+    let (recall, queries) = index.recall(matrix, params.recall_queries, params.seed, threads);
+    Ok((
+        index,
+        IndexReport {
+            recall,
+            queries,
+            seconds,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn random_matrix(rows: usize, dim: usize, seed: u64) -> Matrix {
+        let mut matrix = Matrix::new(rows, dim);
+        matrix.unifrom(1., &mut seeded_rng(seed, 0));
+        matrix
+    }
+
+    fn params() -> HnswParams {
+        HnswParams {
+            m: 8,
+            ef_construction: 100,
+            ..HnswParams::default()
+        }
+    }
+
+    fn cosine(a: &[f32], b: &[f32]) -> f32 {
+        1. - inverse_norm(a) * inverse_norm(b) * a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>()
+    }
+
+    #[test]
+    fn recall_against_exact_search() {
+        let matrix = random_matrix(500, 16, 1);
+        let index = Hnsw::build(&matrix, params(), 2);
+        let queries = random_matrix(50, 16, 2);
+        let k = 10;
+        let mut found = 0;
+        for q in 0..queries.rows() {
+            let query = queries.row(q);
+            let mut exact: Vec<Near> = (0..matrix.rows())
+                .map(|row| Near(cosine(query, matrix.row(row)), row as u32))
+                .collect();
+            exact.sort();
+            let approximate = index.search(&matrix, query, k);
+            assert_eq!(approximate.len(), k);
+            found += exact[..k]
+                .iter()
+                .filter(|near| approximate.iter().any(|&(_, row)| row == near.1 as usize))
+                .count();
+        }
+        let recall = found as f64 / (queries.rows() * k) as f64;
+        assert!(recall > 0.9, "recall {}", recall);
+        let (recall, queries) = index.recall(&matrix, 100, 0, 2);
+        assert_eq!(queries, 100);
+        assert!(recall > 0.9, "recall {}", recall);
+    }
+
+    #[test]
+    fn saved_index_belongs_to_its_vectors() {
+        let matrix = random_matrix(100, 8, 1);
+        let index = Hnsw::build(&matrix, params(), 1);
+        let path = std::env::temp_dir().join(format!("index-{}.hnsw", std::process::id()));
+        let path = path.to_str().unwrap();
+        index.save(path).unwrap();
+        let loaded = Hnsw::load(path, &matrix);
+        let other = Hnsw::load(path, &random_matrix(100, 8, 2));
+        std::fs::remove_file(path).unwrap();
+        let query = matrix.row(3);
+        assert_eq!(
+            loaded.unwrap().search(&matrix, query, 5),
+            index.search(&matrix, query, 5)
+        );
+        assert!(other.is_err());
+    }
+
+    #[test]
+    fn invalid_params() {
+        let matrix = random_matrix(10, 4, 1);
+        for params in [
+            HnswParams { m: 1, ..params() },
+            HnswParams {
+                m: MAX_M + 1,
+                ..params()
+            },
+            HnswParams {
+                ef_construction: 0,
+                ..params()
+            },
+        ] {
+            assert!(build_checked(&matrix, params, 1).is_err());
+        }
+    }
+}
@@ -6,57 +6,51 @@ use model::Model;
 
 mod dictionary;
 use dictionary::Dict;
+pub use dictionary::{Oov, UNK_TOKEN};
 mod matrix;
 use matrix::Matrix;
 mod utils;
-pub use utils::{parse_arguments, Argument, Command};
-
+pub use utils::{parse_arguments, Argument, ArgumentBuilder, ArgumentError, Command};
+
+mod corpus;
+pub use corpus::{Column, CsvFormat, InputFormat};
+mod corpus_cache;
+mod sampler;
+mod lr_schedule;
+mod events;
+mod metrics;
+mod config;
+mod validation;
+pub use events::{Event, Observer};
+pub use lr_schedule::LrSchedule;
+pub use validation::{Scores, ValidMetric};
+mod tokenizer;
+pub use tokenizer::{Punctuation, Tokenizer};
 mod file_utils;
 use file_utils::*;
 mod train;
-pub use train::train;
+pub use train::{
+    build_vocab, build_vocab_with_observer, train, train_sentences, train_sentences_with_observer,
+    train_with_observer, Trainer,
+};
 const SIGMOID_TABLE_SIZE: usize = 512;
 const MAX_SIGMOID: f32 = 8f32;
-const NEGATIVE_TABLE_SIZE: usize = 10000000;
 const LOG_TABLE_SIZE: usize = 512;
 
 mod w2v;
 pub use utils::W2vError;
-use w2v::Word2vec;
+pub use w2v::Word2vec;
+mod serve;
+pub use serve::QueryServer;
+mod hnsw;
+pub use hnsw::{HnswParams, IndexReport};
 #[macro_use]
 extern crate clap;
 
 mod ffi;
 use ffi::*;
+#[cfg(feature = "python")]
+mod python;
 
-use libc::size_t;
-
-#[link(name = "vec_arith")]
-extern "C" {
-    fn simd_dot_product_x4(a: *const f32, b: *const f32, length: size_t) -> f32;
-    fn simd_saxpy(dst: *mut f32, source: *const f32, scale: f32, size: size_t);
-
-    fn saxpy_x4(dst: *mut f32, source: *const f32, scale: f32, size: size_t);
-
-    fn simd_dot_product(a: *const f32, b: *const f32, length: size_t) -> f32;
-
-    fn dot_product(a: *const f32, b: *const f32, length: size_t) -> f32;
-
-    fn saxpy(dst: *mut f32, source: *const f32, scale: f32, size: size_t);
-    /*
-    fn snappy_compress(input: *const u8,
-                       input_length: size_t,
-                       compressed: *mut u8,
-                       compressed_length: *mut size_t) -> c_int;
-    fn snappy_uncompress(compressed: *const u8,
-                         compressed_length: size_t,
-                         uncompressed: *mut u8,
-                         uncompressed_length: *mut size_t) -> c_int;
-    fn snappy_max_compressed_length(source_length: size_t) -> size_t;
-    fn snappy_uncompressed_length(compressed: *const u8,
-                                  compressed_length: size_t,
-                                  result: *mut size_t) -> c_int;
-    fn snappy_validate_compressed_buffer(compressed: *const u8,
-                                         compressed_length: size_t) -> c_int;
-    */
-}
+mod vec_arith;
+use vec_arith::{dot_product, saxpy};
@@ -0,0 +1,125 @@
+use crate::Argument;
+use std::f32::consts::PI;
+
+// how the learning rate falls from --lr to --min_lr over training, after the
+// --warmup part where it rises to --lr
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LrSchedule {
+    Linear,
+    // half a cosine wave, slow at the start and the end
+    Cosine,
+    // multiplied by --lr_decay at every epoch
+    Step,
+    Constant,
+}
+
+impl LrSchedule {
+    pub fn parse(s: &str) -> Option<LrSchedule> {
+        match s {
+            "linear" => Some(LrSchedule::Linear),
+            "cosine" => Some(LrSchedule::Cosine),
+            "step" => Some(LrSchedule::Step),
+            "constant" => Some(LrSchedule::Constant),
+            _ => None,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            LrSchedule::Linear => "linear",
+            LrSchedule::Cosine => "cosine",
+            LrSchedule::Step => "step",
+            LrSchedule::Constant => "constant",
+        }
+    }
+
+    // learning rate at `progress`, the part of the tokens of all epochs that
+    // all threads have trained
+    pub fn lr(self, arg: &Argument, progress: f32) -> f32 {
+        let progress = progress.clamp(0., 1.);
+        if progress < arg.warmup {
+            return arg.min_lr.max(arg.lr * progress / arg.warmup);
+        }
+        let decay = ((progress - arg.warmup) / (1. - arg.warmup).max(f32::EPSILON)).clamp(0., 1.);
+        let lr = match self {
+            LrSchedule::Linear => arg.lr - (arg.lr - arg.min_lr) * decay,
+            LrSchedule::Cosine => {
+                arg.min_lr + (arg.lr - arg.min_lr) * 0.5 * (1. + (PI * decay).cos())
+            }
+            LrSchedule::Step => {
+                let epoch = ((progress * arg.epoch as f32) as i32).min(arg.epoch as i32 - 1);
+                arg.lr * arg.lr_decay.powi(epoch)
+            }
+            LrSchedule::Constant => arg.lr,
+        };
+        lr.max(arg.min_lr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ArgumentBuilder, Command};
+
+    fn args(warmup: f32, lr_decay: f32) -> Argument {
+        ArgumentBuilder::new(String::new(), Command::Train)
+            .lr(0.1)
+            .min_lr(0.001)
+            .epoch(4)
+            .warmup(warmup)
+            .lr_decay(lr_decay)
+            .build()
+            .unwrap()
+    }
+
+    fn assert_near(lr: f32, expected: f32) {
+        assert!((lr - expected).abs() < 1e-6, "{} is not {}", lr, expected);
+    }
+
+    #[test]
+    fn schedules_at_their_ends() {
+        let args = args(0., 0.5);
+        let ends = [
+            (LrSchedule::Linear, 0.001),
+            (LrSchedule::Cosine, 0.001),
+            // the decay of 3 epochs, the last one starts at 0.75
+            (LrSchedule::Step, 0.0125),
+            (LrSchedule::Constant, 0.1),
+        ];
+        for (schedule, end) in ends {
+            assert_near(schedule.lr(&args, 0.), 0.1);
+            assert_near(schedule.lr(&args, 1.), end);
+            // progress past the ends is clamped
+            assert_near(schedule.lr(&args, -1.), 0.1);
+            assert_near(schedule.lr(&args, 2.), end);
+        }
+        assert_near(LrSchedule::Linear.lr(&args, 0.5), 0.0505);
+        assert_near(LrSchedule::Cosine.lr(&args, 0.5), 0.0505);
+        assert_near(LrSchedule::Step.lr(&args, 0.25), 0.05);
+        assert_near(LrSchedule::Step.lr(&args, 0.74), 0.025);
+    }
+
+    #[test]
+    fn step_decay_stops_at_min_lr() {
+        let args = args(0., 0.01);
+        assert_near(LrSchedule::Step.lr(&args, 0.5), 0.001);
+        assert_near(LrSchedule::Step.lr(&args, 1.), 0.001);
+    }
+
+    #[test]
+    fn warmup_rises_to_lr() {
+        let args = args(0.2, 0.5);
+        for schedule in [
+            LrSchedule::Linear,
+            LrSchedule::Cosine,
+            LrSchedule::Step,
+            LrSchedule::Constant,
+        ] {
+            assert_near(schedule.lr(&args, 0.), 0.001);
+            assert_near(schedule.lr(&args, 0.1), 0.05);
+            assert_near(schedule.lr(&args, 0.2), 0.1);
+        }
+        // the decay starts after the warmup
+        assert_near(LrSchedule::Linear.lr(&args, 0.6), 0.0505);
+        assert_near(LrSchedule::Linear.lr(&args, 1.), 0.001);
+    }
+}
@@ -1,19 +1,112 @@
+extern crate rand;
 extern crate word2vec;
-use word2vec::{train, parse_arguments, Command};
+use word2vec::{
+    build_vocab_with_observer, parse_arguments, train_with_observer, ArgumentError, Command, Event,
+    Observer, QueryServer, Word2vec,
+};
 use std::env::args;
+use std::io::{stdout, Write};
+use std::sync::Arc;
+
+// prints the progress of counting and training on the terminal
+struct TerminalProgress {
+    verbose: bool,
+}
+
+impl Observer for TerminalProgress {
+    fn on_event(&self, event: &Event) {
+        match *event {
+            Event::WordsRead(words) => print!("\rRead {}M words", words / 1000000),
+            Event::VocabBuilt { words, tokens, skipped, pruned_below } if self.verbose => {
+                println!("\rRead {} M words", tokens / 1000000);
+                println!("\r{} unique words in total", words);
+                if skipped > 0 {
+                    println!("skipped {} malformed lines", skipped);
+                }
+                if let Some(min_count) = pruned_below {
+                    println!("pruned words seen less than {} times while counting", min_count);
+                }
+            }
+            Event::TrainStarted { seed, .. } if self.verbose => println!("seed {}", seed),
+            Event::Progress { progress, words_per_sec, lr, loss, .. } => print!(
+                "\rProgress:{:.1}% words/sec:{:<7.0} lr:{:.4} loss:{:.5}",
+                progress * 100.,
+                words_per_sec,
+                lr,
+                loss
+            ),
+            Event::EpochEnd { epoch, loss } => println!("\nepoch {} loss:{:.5}", epoch, loss),
+            Event::Validated { scores, best_epoch, .. } => {
+                let mut line = String::from("valid");
+                if let Some(loss) = scores.loss {
+                    line += &format!(" loss:{:.5}", loss);
+                }
+                if let Some(similarity) = scores.similarity {
+                    line += &format!(" similarity:{:.4}", similarity);
+                }
+                if let Some(analogy) = scores.analogy {
+                    line += &format!(" analogy:{:.4}", analogy);
+                }
+                println!("{} best epoch:{}", line, best_epoch);
+            }
+            Event::EarlyStopped { best_epoch, .. } => {
+                println!("stopped early, keeping the vectors of epoch {}", best_epoch)
+            }
+            Event::Finished { seconds } if self.verbose => {
+                println!("\ntotal train time:{} s", seconds as u64)
+            }
+            _ => {}
+        }
+        stdout().flush().unwrap();
+    }
+}
+
 fn main() {
 
     let args_str = args().collect::<Vec<String>>();
     let arguments = parse_arguments(&args_str);
-    if arguments.is_err() {
-        println!("argument error --help for help");
-        return;
-    }
-    let arguments = arguments.unwrap();
+    let mut arguments = match arguments {
+        Ok(arguments) => arguments,
+        // usage, help and version as clap prints them
+        Err(ArgumentError::ParseArg(err)) => err.exit(),
+        Err(err) => {
+            println!("argument error {} --help for help", err);
+            return;
+        }
+    };
+    let observer = Arc::new(TerminalProgress { verbose: arguments.verbose });
 
     if arguments.command == Command::Train {
-        let w2v = train(&arguments).expect("error enconter when training");
+        // picked here so that the saved config reproduces the run
+        arguments.seed.get_or_insert_with(rand::random);
+        arguments
+            .save_config(&format!("{}.config.toml", arguments.output))
+            .expect("error save config");
+        let w2v = train_with_observer(&arguments, observer).expect("error enconter when training");
         w2v.save_vectors(&arguments.output).expect("error save vectors");
 
+    } else if arguments.command == Command::Vocab {
+        build_vocab_with_observer(&arguments, observer)
+            .expect("error enconter when counting words");
+    } else if arguments.command == Command::Serve {
+        let mut model = Word2vec::load(&arguments.input).expect("error load model");
+        model.set_oov(arguments.oov);
+        let words = model.vocab_size();
+        let server = QueryServer::bind(model, &arguments.address).expect("error start server");
+        println!("serving {} words on http://{}", words, server.address());
+        server.run(arguments.nthreads).expect("error serving queries");
+    } else if arguments.command == Command::Index {
+        let mut model = Word2vec::load_vectors(&arguments.input).expect("error load model");
+        let report = model
+            .build_index(arguments.hnsw, arguments.nthreads)
+            .expect("error build index");
+        println!(
+            "indexed {} words in {:.1}s, recall@10 {:.4} over {} words",
+            model.vocab_size(),
+            report.seconds,
+            report.recall,
+            report.queries
+        );
+        model.save_index(&arguments.input).expect("error save index");
     }
 }
@@ -1,11 +1,10 @@
-use rand;
+use rand::{self, Rng};
 #[cfg(feature="blas")]
 use blas_sys::c;
 use rand::distributions::{IndependentSample, Range};
 use std::cell::UnsafeCell;
 
-use crate::simd_dot_product;
-use crate::saxpy;
+use crate::{dot_product, saxpy};
 
 #[derive(Debug)]
 pub struct MatrixWrapper {
@@ -13,7 +12,7 @@ pub struct MatrixWrapper {
 }
 unsafe impl Sync for MatrixWrapper {}
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Matrix {
     row_size: usize,
     mat: Vec<f32>,
@@ -42,11 +41,10 @@ impl Matrix {
     pub fn make_send(self) -> MatrixWrapper {
         MatrixWrapper { inner: UnsafeCell::new(self) }
     }
-    pub fn unifrom(&mut self, bound: f32) {
+    pub fn unifrom<R: Rng>(&mut self, bound: f32, rng: &mut R) {
         let between = Range::new(-bound, bound);
-        let mut rng = rand::thread_rng();
         for v in &mut self.mat {
-            *v = between.ind_sample(&mut rng);
+            *v = between.ind_sample(rng);
         }
 
     }
@@ -61,7 +59,6 @@ impl Matrix {
     pub fn add_row(&mut self, vec: *mut f32, i: usize, mul: f32) {
 
         unsafe {
-            //simd_saxpy(
             saxpy(
                 self.mat.get_unchecked_mut(i*self.row_size),
                 vec,
@@ -75,8 +72,7 @@ impl Matrix {
     #[inline(always)]
     pub fn dot_row(&mut self, vec: *const f32, i: usize) -> f32 {
         unsafe {
-            simd_dot_product(
-            //dot_product(
+            dot_product(
                 self.mat.get_unchecked(i* self.row_size),
                 vec,
                 self.row_size
@@ -87,8 +83,7 @@ impl Matrix {
     #[inline(always)]
     pub fn dot_two_row(&mut self, i: usize, j: usize) -> f32 {
             unsafe {
-                simd_dot_product(
-                //dot_product(
+                dot_product(
                     self.mat.get_unchecked(i* self.row_size),
                     self.mat.get_unchecked(j* self.row_size),
                     self.row_size
@@ -118,5 +113,24 @@ impl Matrix {
     pub fn get_row_unmod(&self, i: usize) -> *const f32 {
         unsafe { self.mat.get_unchecked(i * self.row_size) }
     }
+    // rows of `row_size` values one after another
+    pub fn from_vec(mat: Vec<f32>, row_size: usize) -> Matrix {
+        assert!(row_size > 0 && mat.len().is_multiple_of(row_size));
+        Matrix { row_size, mat }
+    }
+    #[inline]
+    pub fn as_slice(&self) -> &[f32] {
+        &self.mat
+    }
+    pub fn rows(&self) -> usize {
+        self.mat.len() / self.row_size
+    }
+    pub fn row_size(&self) -> usize {
+        self.row_size
+    }
+    #[inline]
+    pub fn row(&self, i: usize) -> &[f32] {
+        &self.mat[i * self.row_size..(i + 1) * self.row_size]
+    }
 
 }
@@ -0,0 +1,190 @@
+use crate::validation::Scores;
+use crate::W2vError;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::Mutex;
+
+// a line of the metrics file
+pub struct Record {
+    // "interval" every --lr_update tokens of the first thread, "epoch" at the
+    // end of each of its epochs
+    pub kind: &'static str,
+    pub epoch: u32,
+    // tokens trained by all threads
+    pub words: usize,
+    pub words_per_sec: f32,
+    pub lr: f32,
+    // mean loss of all threads since the previous record of the same kind
+    pub loss: f64,
+    pub seconds: f64,
+    // scores on the held-out data, for epochs when it is given
+    pub valid: Option<Scores>,
+}
+
+// training metrics as csv when the file name ends in .csv, json lines otherwise
+pub struct MetricsLog {
+    csv: bool,
+    out: Mutex<BufWriter<File>>,
+}
+
+impl MetricsLog {
+    pub fn create(path: &str) -> Result<MetricsLog, W2vError> {
+        let csv = path.ends_with(".csv");
+        let mut out = BufWriter::new(File::create(path)?);
+        if csv {
+            writeln!(
+                out,
+                "kind,epoch,words,words_per_sec,lr,loss,seconds,valid_loss,similarity,analogy"
+            )?;
+        }
+        Ok(MetricsLog { csv, out: Mutex::new(out) })
+    }
+
+    pub fn write(&self, r: &Record) -> Result<(), W2vError> {
+        let valid = r.valid.unwrap_or_default();
+        let scores = [valid.loss, valid.similarity, valid.analogy];
+        let mut out = self.out.lock().unwrap();
+        if self.csv {
+            let [valid_loss, similarity, analogy] = scores.map(|s| number(s, ""));
+            writeln!(
+                out,
+                "{},{},{},{},{},{},{},{},{},{}",
+                r.kind,
+                r.epoch,
+                r.words,
+                r.words_per_sec,
+                r.lr,
+                number(Some(r.loss), ""),
+                r.seconds,
+                valid_loss,
+                similarity,
+                analogy
+            )?;
+        } else {
+            let [valid_loss, similarity, analogy] = scores.map(|s| number(s, "null"));
+            writeln!(
+                out,
+                "{{\"kind\":\"{}\",\"epoch\":{},\"words\":{},\"words_per_sec\":{},\"lr\":{},\
+                 \"loss\":{},\"seconds\":{},\"valid_loss\":{},\"similarity\":{},\"analogy\":{}}}",
+                r.kind,
+                r.epoch,
+                r.words,
+                r.words_per_sec,
+                r.lr,
+                number(Some(r.loss), "null"),
+                r.seconds,
+                valid_loss,
+                similarity,
+                analogy
+            )?;
+        }
+        // flushed every record, so a running training can be plotted
+        out.flush()?;
+        Ok(())
+    }
+}
+
+// `missing` for a score that is not given or not a number, which json lacks
+fn number(value: Option<f64>, missing: &str) -> String {
+    match value {
+        Some(v) if v.is_finite() => v.to_string(),
+        _ => missing.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::fs;
+
+    fn records() -> [Record; 2] {
+        let interval = Record {
+            kind: "interval",
+            epoch: 0,
+            words: 5000,
+            words_per_sec: 2500.,
+            lr: 0.025,
+            loss: 2.5,
+            seconds: 2.,
+            valid: None,
+        };
+        let epoch = Record {
+            kind: "epoch",
+            words: 8000,
+            loss: f64::NAN,
+            valid: Some(Scores {
+                loss: Some(3.),
+                similarity: None,
+                analogy: Some(0.5),
+            }),
+            ..interval
+        };
+        [interval, epoch]
+    }
+
+    fn written(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("{}-{}", std::process::id(), name));
+        let path = path.to_str().unwrap();
+        let log = MetricsLog::create(path).unwrap();
+        for record in &records() {
+            log.write(record).unwrap();
+        }
+        let text = fs::read_to_string(path).unwrap();
+        fs::remove_file(path).unwrap();
+        text
+    }
+
+    #[test]
+    fn csv_rows() {
+        let text = written("metrics.csv");
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "kind,epoch,words,words_per_sec,lr,loss,seconds,valid_loss,similarity,analogy",
+                "interval,0,5000,2500,0.025,2.5,2,,,",
+                "epoch,0,8000,2500,0.025,,2,3,,0.5",
+            ]
+        );
+    }
+
+    #[test]
+    fn json_lines() {
+        let text = written("metrics.jsonl");
+        let rows: Vec<Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(rows.len(), 2);
+        let columns = [
+            "kind",
+            "epoch",
+            "words",
+            "words_per_sec",
+            "lr",
+            "loss",
+            "seconds",
+            "valid_loss",
+            "similarity",
+            "analogy",
+        ];
+        // the key order depends on the features of serde_json
+        let mut columns = columns.to_vec();
+        columns.sort_unstable();
+        for row in &rows {
+            let mut keys: Vec<&String> = row.as_object().unwrap().keys().collect();
+            keys.sort_unstable();
+            assert_eq!(keys, columns);
+        }
+        assert_eq!(rows[0]["kind"], "interval");
+        assert_eq!(rows[0]["words"], 5000);
+        assert_eq!(rows[0]["loss"], 2.5);
+        assert_eq!(rows[0]["valid_loss"], Value::Null);
+        assert_eq!(rows[1]["kind"], "epoch");
+        assert_eq!(rows[1]["loss"], Value::Null);
+        assert_eq!(rows[1]["valid_loss"], 3.);
+        assert_eq!(rows[1]["similarity"], Value::Null);
+        assert_eq!(rows[1]["analogy"], 0.5);
+    }
+}
@@ -1,8 +1,10 @@
 extern crate rand;
 use libc;
+use rand::XorShiftRng;
 use std::sync::Arc;
 use std::mem::size_of;
 use crate::matrix::Matrix;
+use crate::sampler::AliasTable;
 use crate::saxpy;
 
 
@@ -28,6 +30,24 @@ fn init_log_table() -> [f32; LOG_TABLE_SIZE + 1] {
     log_table
 }
 
+// loss of a number of training samples, added up over threads
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LossSum {
+    pub loss: f64,
+    pub samples: u64,
+}
+
+impl LossSum {
+    pub fn add(&mut self, other: LossSum) {
+        self.loss += other.loss;
+        self.samples += other.samples;
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.loss / self.samples as f64
+    }
+}
+
 pub struct Model<'a> {
     pub input: &'a mut Matrix,
     output: &'a mut Matrix,
@@ -35,10 +55,11 @@ pub struct Model<'a> {
     lr: f32,
     neg: usize,
     grad_: Vec<f32>,
-    neg_pos: usize,
     sigmoid_table: [f32; SIGMOID_TABLE_SIZE + 1],
     log_table: [f32; LOG_TABLE_SIZE + 1],
-    negative_table: Arc<Vec<usize>>,
+    negative_table: Arc<AliasTable>,
+    // draws the negative samples of this thread
+    rng: XorShiftRng,
     loss: f64,
     nsamples: u64, /* t_loss: f32,
                     * t_sample: u64, */
@@ -50,7 +71,8 @@ impl<'a> Model<'a> {
                lr: f32,
                // tid: u32,
                neg: usize,
-               neg_table: Arc<Vec<usize>>)
+               neg_table: Arc<AliasTable>,
+               rng: XorShiftRng)
                -> Model<'a> {
         Model {
             input: input,
@@ -59,10 +81,10 @@ impl<'a> Model<'a> {
             lr: lr,
             neg: neg,
             grad_: vec![0f32;dim],
-            neg_pos: 0,
             sigmoid_table: init_sigmoid_table(),
             log_table: init_log_table(),
             negative_table: neg_table,
+            rng,
             loss: 0.,
             nsamples: 0, /* t_sample: 0,
                           * t_loss: 0., */
@@ -89,9 +111,16 @@ impl<'a> Model<'a> {
             unsafe { *self.sigmoid_table.get_unchecked(i as usize) }
         }
     }
-    #[inline]
-    pub fn get_loss(&self) -> f64 {
-        self.loss / self.nsamples as f64
+    // loss summed since the previous call, which starts a new sum
+    pub fn take_loss(&mut self) -> LossSum {
+        let sum = LossSum { loss: self.loss, samples: self.nsamples };
+        self.loss = 0.;
+        self.nsamples = 0;
+        sum
+    }
+    // the vectors being trained, to evaluate them between epochs
+    pub fn matrices(&self) -> (&Matrix, &Matrix) {
+        (self.input, self.output)
     }
     #[inline(always)]
     pub fn set_lr(&mut self, lr: f32) {
@@ -138,8 +167,7 @@ impl<'a> Model<'a> {
     }
     fn get_negative(&mut self, target: usize) -> usize {
         loop {
-            let negative = self.negative_table[self.neg_pos];
-            self.neg_pos = (self.neg_pos + 1) % self.negative_table.len();
+            let negative = self.negative_table.sample(&mut self.rng);
             if target != negative {
                 return negative;
             }
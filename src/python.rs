@@ -0,0 +1,356 @@
+// the python extension module: training with keyword arguments, loading saved
+// models and querying them with numpy arrays
+use crate::{
+    ArgumentBuilder, ArgumentError, Command, InputFormat, LrSchedule, Oov, Punctuation, Tokenizer,
+    Trainer, ValidMetric, W2vError, Word2vec,
+};
+use numpy::{PyArray1, PyArrayMethods};
+use pyo3::exceptions::{PyIOError, PyKeyError, PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+
+fn argument_error(err: ArgumentError) -> PyErr {
+    PyValueError::new_err(err.to_string())
+}
+
+fn w2v_error(err: W2vError) -> PyErr {
+    match err {
+        W2vError::OutOfVocabulary(word) => PyKeyError::new_err(word),
+        W2vError::File(err) => PyIOError::new_err(err.to_string()),
+        W2vError::InvalidInput(reason) => PyValueError::new_err(reason),
+        err => PyRuntimeError::new_err(err.to_string()),
+    }
+}
+
+fn option<T>(name: &str, value: &str, parse: fn(&str) -> Option<T>) -> PyResult<T> {
+    parse(value).ok_or_else(|| PyValueError::new_err(format!("invalid {}: {}", name, value)))
+}
+
+// an iterable of iterables of words, copied before the lock is released
+fn read_sentences(sentences: &Bound<'_, PyAny>) -> PyResult<Vec<Vec<String>>> {
+    let mut read = Vec::new();
+    for sentence in sentences.try_iter()? {
+        let sentence = sentence?;
+        if sentence.is_instance_of::<pyo3::types::PyString>() {
+            return Err(PyValueError::new_err(
+                "sentences are lists of words, not strings",
+            ));
+        }
+        let mut words = Vec::new();
+        for word in sentence.try_iter()? {
+            words.push(word?.extract::<String>()?);
+        }
+        read.push(words);
+    }
+    Ok(read)
+}
+
+/// Word vectors trained with `train` or read with `load`.
+#[pyclass(name = "Word2Vec", module = "word2vec", frozen)]
+struct PyWord2vec {
+    model: Word2vec,
+}
+
+#[pymethods]
+impl PyWord2vec {
+    /// reads a model saved by `save` or the command line
+    #[staticmethod]
+    fn load(py: Python<'_>, path: String) -> PyResult<PyWord2vec> {
+        let model = py.detach(|| Word2vec::load(&path)).map_err(w2v_error)?;
+        Ok(PyWord2vec { model })
+    }
+
+    /// writes the vocabulary to `path`, the vectors to `path.vec` and the
+    /// tokenizer to `path.tokenizer`
+    fn save(&self, py: Python<'_>, path: String) -> PyResult<()> {
+        py.detach(|| self.model.save_vectors(&path))
+            .map(|_| ())
+            .map_err(w2v_error)
+    }
+
+    /// the vector of `word` as a numpy array
+    fn __getitem__<'py>(&self, py: Python<'py>, word: &str) -> PyResult<Bound<'py, PyArray1<f32>>> {
+        match self.model.lookup(word).map_err(w2v_error)? {
+            Some(vector) => Ok(PyArray1::from_slice(py, &vector)),
+            None => Err(PyKeyError::new_err(word.to_string())),
+        }
+    }
+
+    fn __contains__(&self, word: &str) -> bool {
+        self.model.get_index(word).is_some()
+    }
+
+    fn __len__(&self) -> usize {
+        self.model.vocab_size()
+    }
+
+    /// the `topn` words closest to `word` by cosine similarity, as
+    /// `(word, similarity)` pairs
+    #[pyo3(signature = (word, topn = 10))]
+    fn most_similar(
+        &self,
+        py: Python<'_>,
+        word: &str,
+        topn: usize,
+    ) -> PyResult<Vec<(String, f32)>> {
+        let similar = py
+            .detach(|| self.model.most_similar(word, Some(topn)))
+            .map_err(w2v_error)?;
+        Ok(similar
+            .into_iter()
+            .map(|(score, word)| (word, score))
+            .collect())
+    }
+
+    /// cosine similarity of two words, None when one of them is skipped
+    fn similarity(&self, a: &str, b: &str) -> PyResult<Option<f32>> {
+        self.model.similarity(a, b).map_err(w2v_error)
+    }
+
+    /// index of `word` in the vocabulary and the rows of `vectors`
+    fn index(&self, word: &str) -> Option<usize> {
+        self.model.get_index(word)
+    }
+
+    /// splits text into words the way the model was trained
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        self.model.tokenize(text)
+    }
+
+    /// the words, by index
+    #[getter]
+    fn vocab(&self) -> Vec<String> {
+        (0..self.model.vocab_size())
+            .filter_map(|i| self.model.get_word(i).map(str::to_string))
+            .collect()
+    }
+
+    #[getter]
+    fn vector_size(&self) -> usize {
+        self.model.dim()
+    }
+
+    /// a copy of all vectors, one row per word
+    #[getter]
+    fn vectors<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, numpy::PyArray2<f32>>> {
+        PyArray1::from_slice(py, self.model.vectors())
+            .reshape([self.model.vocab_size(), self.model.dim()])
+    }
+
+    /// how words out of the vocabulary are looked up: error, skip, zero or unk
+    #[getter]
+    fn oov(&self) -> &'static str {
+        self.model.oov().name()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Word2Vec(vocab={}, vector_size={})",
+            self.model.vocab_size(),
+            self.model.dim()
+        )
+    }
+}
+
+/// Trains word vectors from the corpus file `input`, or from `sentences`, an
+/// iterable of lists of words. The options are those of the command line,
+/// left to their defaults when not given. The lock is released while training.
+#[pyfunction]
+#[pyo3(signature = (
+    input = None,
+    sentences = None,
+    *,
+    dim = None,
+    win = None,
+    epoch = None,
+    neg = None,
+    threads = None,
+    min_count = None,
+    threshold = None,
+    lr = None,
+    min_lr = None,
+    lr_update = None,
+    lr_schedule = None,
+    warmup = None,
+    lr_decay = None,
+    neg_pow = None,
+    seed = None,
+    deterministic = false,
+    format = None,
+    field = None,
+    max_rows = None,
+    max_vocab_size = None,
+    max_final_vocab = None,
+    read_vocab = None,
+    noise_weights = None,
+    corpus_cache = None,
+    oov = None,
+    lowercase = false,
+    nfkc = false,
+    punctuation = None,
+    unicode_words = false,
+    normalize_numbers = false,
+    metrics = None,
+    valid = None,
+    valid_similarity = None,
+    valid_analogy = None,
+    valid_metric = None,
+    patience = None,
+))]
+#[allow(clippy::too_many_arguments)]
+fn train(
+    py: Python<'_>,
+    input: Option<String>,
+    sentences: Option<&Bound<'_, PyAny>>,
+    dim: Option<usize>,
+    win: Option<usize>,
+    epoch: Option<u32>,
+    neg: Option<usize>,
+    threads: Option<u32>,
+    min_count: Option<u32>,
+    threshold: Option<f32>,
+    lr: Option<f32>,
+    min_lr: Option<f32>,
+    lr_update: Option<u32>,
+    lr_schedule: Option<&str>,
+    warmup: Option<f32>,
+    lr_decay: Option<f32>,
+    neg_pow: Option<f32>,
+    seed: Option<u64>,
+    deterministic: bool,
+    format: Option<&str>,
+    field: Option<&str>,
+    max_rows: Option<usize>,
+    max_vocab_size: Option<usize>,
+    max_final_vocab: Option<usize>,
+    read_vocab: Option<String>,
+    noise_weights: Option<String>,
+    corpus_cache: Option<String>,
+    oov: Option<&str>,
+    lowercase: bool,
+    nfkc: bool,
+    punctuation: Option<&str>,
+    unicode_words: bool,
+    normalize_numbers: bool,
+    metrics: Option<String>,
+    valid: Option<String>,
+    valid_similarity: Option<String>,
+    valid_analogy: Option<String>,
+    valid_metric: Option<&str>,
+    patience: Option<u32>,
+) -> PyResult<PyWord2vec> {
+    let sentences = match (&input, sentences) {
+        (Some(_), None) => None,
+        (None, Some(sentences)) => Some(read_sentences(sentences)?),
+        _ => return Err(PyValueError::new_err("give either input or sentences")),
+    };
+    let mut builder = ArgumentBuilder::new(input.clone().unwrap_or_default(), Command::Train);
+    match input {
+        Some(ref input) if format.is_some() || field.is_some() => {
+            let csv = Default::default();
+            builder.format(InputFormat::from_args(input, format, field, csv));
+        }
+        None if format.is_some() || field.is_some() => {
+            return Err(PyValueError::new_err("format and field apply to input, not sentences"));
+        }
+        _ => {}
+    }
+    let mut tokenizer = Tokenizer {
+        lowercase,
+        nfkc,
+        unicode_words,
+        numbers: normalize_numbers,
+        ..Default::default()
+    };
+    if let Some(punctuation) = punctuation {
+        tokenizer.punctuation = option("punctuation", punctuation, Punctuation::parse)?;
+    }
+    builder
+        .tokenizer(tokenizer)
+        .seed(seed)
+        .deterministic(deterministic)
+        .max_rows(max_rows)
+        .max_vocab_size(max_vocab_size)
+        .max_final_vocab(max_final_vocab)
+        .read_vocab(read_vocab)
+        .noise_weights(noise_weights)
+        .corpus_cache(corpus_cache)
+        .metrics(metrics)
+        .valid(valid)
+        .valid_similarity(valid_similarity)
+        .valid_analogy(valid_analogy)
+        .patience(patience);
+    if let Some(dim) = dim {
+        builder.dim(dim);
+    }
+    if let Some(win) = win {
+        builder.win(win);
+    }
+    if let Some(epoch) = epoch {
+        builder.epoch(epoch);
+    }
+    if let Some(neg) = neg {
+        builder.neg(neg);
+    }
+    if let Some(threads) = threads {
+        builder.threads(threads);
+    }
+    if let Some(min_count) = min_count {
+        builder.min_count(min_count);
+    }
+    if let Some(threshold) = threshold {
+        builder.threshold(threshold);
+    }
+    if let Some(lr) = lr {
+        builder.lr(lr);
+    }
+    if let Some(min_lr) = min_lr {
+        builder.min_lr(min_lr);
+    }
+    if let Some(lr_update) = lr_update {
+        builder.lr_update(lr_update);
+    }
+    if let Some(lr_schedule) = lr_schedule {
+        builder.lr_schedule(option("lr_schedule", lr_schedule, LrSchedule::parse)?);
+    }
+    if let Some(warmup) = warmup {
+        builder.warmup(warmup);
+    }
+    if let Some(lr_decay) = lr_decay {
+        builder.lr_decay(lr_decay);
+    }
+    if let Some(neg_pow) = neg_pow {
+        builder.neg_pow(neg_pow);
+    }
+    if let Some(oov) = oov {
+        builder.oov(option("oov", oov, Oov::parse)?);
+    }
+    if let Some(valid_metric) = valid_metric {
+        builder.valid_metric(Some(option(
+            "valid_metric",
+            valid_metric,
+            ValidMetric::parse,
+        )?));
+    }
+    let trainer = Trainer::new(builder.build().map_err(argument_error)?).map_err(argument_error)?;
+    let model = py
+        .detach(|| match sentences {
+            Some(ref sentences) => trainer.train_sentences(sentences),
+            None => trainer.train(),
+        })
+        .map_err(w2v_error)?;
+    Ok(PyWord2vec { model })
+}
+
+/// reads a model saved by `Word2Vec.save` or the command line
+#[pyfunction]
+fn load(py: Python<'_>, path: String) -> PyResult<PyWord2vec> {
+    PyWord2vec::load(py, path)
+}
+
+#[pymodule]
+fn word2vec(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyWord2vec>()?;
+    m.add_function(wrap_pyfunction!(train, m)?)?;
+    m.add_function(wrap_pyfunction!(load, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,111 @@
+use crate::W2vError;
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+// independent generators derived from one seed, numbered by `stream`
+pub fn seeded_rng(seed: u64, stream: u64) -> XorShiftRng {
+    // splitmix64, so that close seeds and streams give unrelated states
+    let mut x = seed ^ stream.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    let mut next = || {
+        x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let z = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        let z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    };
+    let (a, b) = (next(), next());
+    // xorshift needs a state that is not all zeros
+    XorShiftRng::from_seed([a as u32, (a >> 32) as u32, b as u32, (b >> 32) as u32 | 1])
+}
+
+// Walker's alias method: draws index i with probability weights[i] / sum in
+// O(1) from two tables of the size of the vocabulary
+#[derive(Debug)]
+pub struct AliasTable {
+    // chance of keeping the drawn column instead of taking its alias
+    prob: Vec<f32>,
+    alias: Vec<u32>,
+}
+
+impl AliasTable {
+    // Vose's construction. words of weight 0 are never drawn, unless all
+    // weights are 0 and every word is equally likely. there must be at
+    // least one word to draw
+    pub fn new(weights: &[f64]) -> Result<AliasTable, W2vError> {
+        let n = weights.len();
+        if n == 0 {
+            return Err(W2vError::InvalidInput(
+                "no weights to sample from".to_string(),
+            ));
+        }
+        let total: f64 = weights.iter().sum();
+        let mut scaled: Vec<f64> = if total > 0. {
+            weights.iter().map(|w| w * n as f64 / total).collect()
+        } else {
+            vec![1.; n]
+        };
+        let mut prob = vec![1f32; n];
+        let mut alias: Vec<u32> = (0..n as u32).collect();
+        let (mut small, mut large): (Vec<usize>, Vec<usize>) =
+            (0..n).partition(|&i| scaled[i] < 1.);
+        while let (Some(&s), Some(&l)) = (small.last(), large.last()) {
+            small.pop();
+            prob[s] = scaled[s] as f32;
+            alias[s] = l as u32;
+            scaled[l] -= 1. - scaled[s];
+            if scaled[l] < 1. {
+                large.pop();
+                small.push(l);
+            }
+        }
+        // what is left is 1 up to rounding
+        Ok(AliasTable { prob, alias })
+    }
+
+    #[inline]
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> usize {
+        // the high bits of a 64 bit product pick the column without a division
+        let i = ((rng.next_u32() as u64 * self.prob.len() as u64) >> 32) as usize;
+        let u = (rng.next_u32() >> 8) as f32 / (1u32 << 24) as f32;
+        if u < self.prob[i] {
+            i
+        } else {
+            self.alias[i] as usize
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frequencies(table: &AliasTable, n: usize) -> Vec<f64> {
+        let mut rng = seeded_rng(7, 0);
+        let mut counts = vec![0usize; table.prob.len()];
+        for _ in 0..n {
+            counts[table.sample(&mut rng)] += 1;
+        }
+        counts.iter().map(|&c| c as f64 / n as f64).collect()
+    }
+
+    #[test]
+    fn draws_by_weight() {
+        let weights = [1., 2., 0., 3., 4.];
+        let freqs = frequencies(&AliasTable::new(&weights).unwrap(), 200_000);
+        assert_eq!(freqs[2], 0.);
+        for (freq, weight) in freqs.iter().zip(&weights) {
+            assert!((freq - weight / 10.).abs() < 0.01, "{:?}", freqs);
+        }
+    }
+
+    #[test]
+    fn all_zero_weights_are_uniform() {
+        let freqs = frequencies(&AliasTable::new(&[0.; 4]).unwrap(), 100_000);
+        for freq in freqs {
+            assert!((freq - 0.25).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn empty() {
+        assert!(AliasTable::new(&[]).is_err());
+    }
+}
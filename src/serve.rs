@@ -0,0 +1,268 @@
+// answers json queries to a model over http, the model shared read-only by
+// the threads taking requests:
+//   GET  /info                                   vocabulary size and dim
+//   POST /vector       {"word": w}               its vector
+//   POST /vectors      {"words": [w, ..]}         their vectors, null when missing
+//   POST /similarity   {"a": w, "b": w}           cosine similarity
+//   POST /most_similar {"word": w, "topn": n}     closest words
+//   POST /analogy      {"a", "b", "c", "topn"}    closest words to b - a + c
+use crate::{W2vError, Word2vec};
+use serde_json::{json, Value};
+use std::io::Read;
+use std::sync::Arc;
+use std::thread;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+// larger request bodies are refused
+const MAX_BODY: u64 = 16 << 20;
+const DEFAULT_TOPN: usize = 10;
+
+/// Serves the queries of the module comment to one model, see the serve
+/// subcommand.
+pub struct QueryServer {
+    server: Arc<Server>,
+    model: Arc<Word2vec>,
+}
+
+// a status and its json body
+type Reply = (u16, Value);
+
+fn error(status: u16, message: &str) -> Reply {
+    (status, json!({ "error": message }))
+}
+
+fn query_error(err: W2vError) -> Reply {
+    match err {
+        W2vError::OutOfVocabulary(_) => error(404, &err.to_string()),
+        _ => error(500, &err.to_string()),
+    }
+}
+
+// through the shortest decimal of the f32, not its exact f64 value
+fn number(x: f32) -> Value {
+    x.to_string()
+        .parse::<f64>()
+        .map_or(Value::Null, Value::from)
+}
+
+fn vector(v: &[f32]) -> Value {
+    Value::Array(v.iter().map(|&x| number(x)).collect())
+}
+
+fn words(similar: Vec<(f32, String)>) -> Value {
+    Value::Array(
+        similar
+            .into_iter()
+            .map(|(score, word)| json!({ "word": word, "similarity": number(score) }))
+            .collect(),
+    )
+}
+
+fn field<'a>(body: &'a Value, key: &str) -> Result<&'a str, Reply> {
+    body.get(key)
+        .and_then(Value::as_str)
+        .ok_or_else(|| error(400, &format!("{} must be a string", key)))
+}
+
+// at most the size of the vocabulary, more could not be answered anyway
+fn topn(model: &Word2vec, body: &Value) -> Result<usize, Reply> {
+    match body.get("topn") {
+        None | Some(Value::Null) => Ok(DEFAULT_TOPN),
+        Some(n) => n
+            .as_u64()
+            .filter(|&n| n > 0)
+            .map(|n| n.min(model.vocab_size() as u64) as usize)
+            .ok_or_else(|| error(400, "topn must be a positive integer")),
+    }
+}
+
+fn answer(model: &Word2vec, method: &Method, path: &str, body: &str) -> Result<Reply, Reply> {
+    let post = *method == Method::Post;
+    let body = || -> Result<Value, Reply> {
+        serde_json::from_str(body).map_err(|e| error(400, &format!("invalid json: {}", e)))
+    };
+    let reply = match path {
+        "/info" if *method == Method::Get => json!({
+            "vocab_size": model.vocab_size(),
+            "dim": model.dim(),
+            "oov": model.oov().name(),
+        }),
+        "/vector" if post => {
+            let body = body()?;
+            let word = field(&body, "word")?;
+            match model.lookup(word).map_err(query_error)? {
+                Some(v) => json!({ "word": word, "vector": vector(&v) }),
+                None => json!({ "word": word, "vector": null }),
+            }
+        }
+        "/vectors" if post => {
+            let body = body()?;
+            let words = body
+                .get("words")
+                .and_then(Value::as_array)
+                .ok_or_else(|| error(400, "words must be an array of strings"))?;
+            let mut vectors = Vec::with_capacity(words.len());
+            for word in words {
+                let word = word
+                    .as_str()
+                    .ok_or_else(|| error(400, "words must be an array of strings"))?;
+                // one missing word does not fail the others
+                vectors.push(match model.lookup(word) {
+                    Ok(Some(v)) => vector(&v),
+                    Ok(None) | Err(W2vError::OutOfVocabulary(_)) => Value::Null,
+                    Err(err) => return Err(query_error(err)),
+                });
+            }
+            json!({ "vectors": vectors })
+        }
+        "/similarity" if post => {
+            let body = body()?;
+            let (a, b) = (field(&body, "a")?, field(&body, "b")?);
+            let similarity = model.similarity(a, b).map_err(query_error)?;
+            json!({ "similarity": similarity.map_or(Value::Null, number) })
+        }
+        "/most_similar" if post => {
+            let body = body()?;
+            let similar = model
+                .most_similar(field(&body, "word")?, Some(topn(model, &body)?))
+                .map_err(query_error)?;
+            json!({ "similar": words(similar) })
+        }
+        "/analogy" if post => {
+            let body = body()?;
+            let (a, b, c) = (field(&body, "a")?, field(&body, "b")?, field(&body, "c")?);
+            let similar = model
+                .analogy(a, b, c, Some(topn(model, &body)?))
+                .map_err(query_error)?;
+            json!({ "similar": words(similar) })
+        }
+        "/info" | "/vector" | "/vectors" | "/similarity" | "/most_similar" | "/analogy" => {
+            return Err(error(405, "method not allowed"))
+        }
+        _ => return Err(error(404, "no such endpoint")),
+    };
+    Ok((200, reply))
+}
+
+fn handle(model: &Word2vec, mut request: Request) {
+    let mut body = String::new();
+    let read = request
+        .as_reader()
+        .take(MAX_BODY + 1)
+        .read_to_string(&mut body);
+    let (status, reply) = match read {
+        Err(_) => error(400, "the body is not utf-8"),
+        Ok(_) if body.len() as u64 > MAX_BODY => error(413, "the body is too large"),
+        Ok(_) => {
+            let path = request.url().split('?').next().unwrap_or("");
+            answer(model, request.method(), path, &body).unwrap_or_else(|e| e)
+        }
+    };
+    let content_type =
+        Header::from_bytes("Content-Type", "application/json").expect("a valid header");
+    let response = Response::from_string(reply.to_string())
+        .with_status_code(status)
+        .with_header(content_type);
+    // the client may be gone already
+    let _ = request.respond(response);
+}
+
+impl QueryServer {
+    /// listens on `address`, a host:port like 127.0.0.1:8080, port 0 for any
+    /// free one
+    pub fn bind(model: Word2vec, address: &str) -> Result<QueryServer, W2vError> {
+        let server = Server::http(address)
+            .map_err(|e| W2vError::InvalidInput(format!("cannot listen on {}: {}", address, e)))?;
+        Ok(QueryServer {
+            server: Arc::new(server),
+            model: Arc::new(model),
+        })
+    }
+
+    /// the address it listens on
+    pub fn address(&self) -> String {
+        self.server.server_addr().to_string()
+    }
+
+    /// answers requests on `threads` threads until the server fails
+    pub fn run(self, threads: u32) -> Result<(), W2vError> {
+        let handles: Vec<_> = (0..threads.max(1))
+            .map(|_| {
+                let (server, model) = (self.server.clone(), self.model.clone());
+                thread::spawn(move || -> Result<(), W2vError> {
+                    loop {
+                        handle(&model, server.recv()?);
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Dict, Matrix, Tokenizer};
+
+    fn model() -> Word2vec {
+        let words = ["x", "y", "-x", "-y", "xy"];
+        let vectors = vec![1., 0., 0., 1., -1., 0., 0., -1., 1., 1.];
+        let counts = words.iter().map(|w| (w.to_string(), 1)).collect();
+        let dict = Dict::from_saved(counts, Tokenizer::default());
+        Word2vec::new(Matrix::from_vec(vectors, 2), Matrix::new(0, 2), 2, dict)
+    }
+
+    fn post(path: &str, body: &str) -> Reply {
+        answer(&model(), &Method::Post, path, body).unwrap_or_else(|e| e)
+    }
+
+    #[test]
+    fn queries() {
+        let (status, info) = answer(&model(), &Method::Get, "/info", "").unwrap();
+        assert_eq!(status, 200);
+        assert_eq!(info, json!({ "vocab_size": 5, "dim": 2, "oov": "error" }));
+        let (status, vector) = post("/vector", r#"{"word": "xy"}"#);
+        assert_eq!(
+            (status, vector),
+            (200, json!({ "word": "xy", "vector": [1.0, 1.0] }))
+        );
+        let (_, vectors) = post("/vectors", r#"{"words": ["x", "z"]}"#);
+        assert_eq!(vectors, json!({ "vectors": [[1.0, 0.0], null] }));
+        let (_, similarity) = post("/similarity", r#"{"a": "x", "b": "-x"}"#);
+        assert_eq!(similarity, json!({ "similarity": -1.0 }));
+        let (_, similar) = post("/most_similar", r#"{"word": "x", "topn": 1}"#);
+        assert_eq!(similar["similar"][0]["word"], "xy");
+        let (_, similar) = post("/analogy", r#"{"a": "y", "b": "xy", "c": "-x"}"#);
+        assert_eq!(similar["similar"][0]["word"], "-y");
+    }
+
+    #[test]
+    fn topn_is_positive_and_bounded() {
+        let huge = format!(r#"{{"word": "x", "topn": {}}}"#, u64::MAX);
+        let (status, similar) = post("/most_similar", &huge);
+        assert_eq!(status, 200);
+        assert_eq!(similar["similar"].as_array().unwrap().len(), 4);
+        for topn in ["0", "-1", "1.5", "\"3\""] {
+            let body = format!(r#"{{"word": "x", "topn": {}}}"#, topn);
+            assert_eq!(post("/most_similar", &body).0, 400, "{}", topn);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(post("/vector", r#"{"word": "z"}"#).0, 404);
+        assert_eq!(post("/vector", "{").0, 400);
+        assert_eq!(post("/vector", r#"{"word": 1}"#).0, 400);
+        assert_eq!(post("/vectors", r#"{"words": "x"}"#).0, 400);
+        assert_eq!(post("/info", "").0, 405);
+        assert_eq!(post("/nothing", "{}").0, 404);
+        assert_eq!(
+            answer(&model(), &Method::Get, "/vector", "").unwrap_err().0,
+            405
+        );
+    }
+}
@@ -0,0 +1,28 @@
+# smoke test of the python module, run after `maturin develop`:
+# python src/test_python.py
+import os
+import tempfile
+
+import word2vec
+
+sentences = [["the", "cat", "sat", "on", "the", "mat"], ["a", "dog", "sat", "on", "a", "log"]] * 50
+model = word2vec.train(sentences=sentences, dim=10, epoch=2, min_count=1, threads=1, seed=1)
+assert len(model) == 8 and "cat" in model and "bird" not in model
+assert model.vector_size == 10
+
+similar = model.most_similar("cat", topn=3)
+assert len(similar) == 3 and all(word != "cat" for word, _ in similar)
+
+with tempfile.TemporaryDirectory() as directory:
+    path = os.path.join(directory, "model")
+    model.save(path)
+    loaded = word2vec.load(path)
+    assert loaded.vocab == model.vocab
+    assert loaded.most_similar("cat", topn=3) == similar
+
+try:
+    model.most_similar("bird")
+    raise AssertionError("most_similar of a word out of the vocabulary")
+except KeyError:
+    pass
+print("ok")
@@ -0,0 +1,271 @@
+use crate::W2vError;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
+
+// every token that is a number when numbers are normalized
+pub const NUMBER_TOKEN: &str = "<num>";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Punctuation {
+    Keep,
+    // drop punctuation around words
+    Strip,
+    // make punctuation around words tokens of their own
+    Split,
+}
+
+// turns text into words, the same way for counting, training and queries
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tokenizer {
+    pub lowercase: bool,
+    pub nfkc: bool,
+    pub punctuation: Punctuation,
+    // split at unicode word boundaries instead of whitespace
+    pub unicode_words: bool,
+    pub numbers: bool,
+}
+
+impl Default for Tokenizer {
+    fn default() -> Tokenizer {
+        Tokenizer {
+            lowercase: false,
+            nfkc: false,
+            punctuation: Punctuation::Keep,
+            unicode_words: false,
+            numbers: false,
+        }
+    }
+}
+
+fn is_punctuation(c: char) -> bool {
+    !c.is_alphanumeric() && !c.is_whitespace()
+}
+
+fn is_number(token: &str) -> bool {
+    token.chars().any(char::is_numeric)
+        && token
+            .chars()
+            .all(|c| c.is_numeric() || c == '.' || c == ',' || c == '-' || c == '+')
+}
+
+impl Punctuation {
+    pub fn parse(s: &str) -> Option<Punctuation> {
+        match s {
+            "keep" => Some(Punctuation::Keep),
+            "strip" => Some(Punctuation::Strip),
+            "split" => Some(Punctuation::Split),
+            _ => None,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Punctuation::Keep => "keep",
+            Punctuation::Strip => "strip",
+            Punctuation::Split => "split",
+        }
+    }
+}
+
+impl Tokenizer {
+    #[inline]
+    pub fn tokenize<F: FnMut(&str)>(&self, text: &str, mut f: F) {
+        if *self == Tokenizer::default() {
+            for word in text.split_whitespace() {
+                f(word);
+            }
+            return;
+        }
+        let text = self.normalize(text);
+        let mut emit = |token: &str| {
+            if self.numbers && is_number(token) {
+                f(NUMBER_TOKEN)
+            } else if !token.is_empty() {
+                f(token)
+            }
+        };
+        if self.unicode_words {
+            for segment in text.split_word_bounds() {
+                let punctuation = segment.chars().all(is_punctuation);
+                if segment.chars().all(char::is_whitespace)
+                    || (punctuation && self.punctuation == Punctuation::Strip)
+                {
+                    continue;
+                }
+                emit(segment);
+            }
+            return;
+        }
+        for word in text.split_whitespace() {
+            match self.punctuation {
+                Punctuation::Keep => emit(word),
+                Punctuation::Strip => emit(word.trim_matches(is_punctuation)),
+                Punctuation::Split => {
+                    let start = word.len() - word.trim_start_matches(is_punctuation).len();
+                    let core = word[start..].trim_end_matches(is_punctuation);
+                    let end = start + core.len();
+                    for (i, c) in word[..start].char_indices() {
+                        emit(&word[i..i + c.len_utf8()]);
+                    }
+                    emit(core);
+                    for (i, c) in word[end..].char_indices() {
+                        emit(&word[end + i..end + i + c.len_utf8()]);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn tokens(&self, text: &str) -> Vec<String> {
+        let mut tokens = Vec::new();
+        self.tokenize(text, |token| tokens.push(token.to_string()));
+        tokens
+    }
+
+    // a word of a query as it was counted: the one token it gives, or only
+    // normalized when it gives none or several
+    pub fn word<'a>(&self, word: &'a str) -> Cow<'a, str> {
+        if *self == Tokenizer::default() {
+            return Cow::Borrowed(word);
+        }
+        let mut tokens = self.tokens(word);
+        match tokens.len() {
+            1 => Cow::Owned(tokens.pop().unwrap_or_default()),
+            _ => self.normalize(word),
+        }
+    }
+
+    fn normalize<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(text);
+        if self.nfkc {
+            text = Cow::Owned(text.nfkc().collect());
+        }
+        if self.lowercase {
+            text = Cow::Owned(text.to_lowercase());
+        }
+        text
+    }
+
+    // settings as `name=value` lines, saved next to the model
+    pub fn save(&self, filename: &str) -> Result<(), W2vError> {
+        let mut file = File::create(filename)?;
+        writeln!(file, "lowercase={}", self.lowercase)?;
+        writeln!(file, "nfkc={}", self.nfkc)?;
+        writeln!(file, "punctuation={}", self.punctuation.name())?;
+        writeln!(file, "unicode_words={}", self.unicode_words)?;
+        writeln!(file, "numbers={}", self.numbers)?;
+        Ok(())
+    }
+
+    pub fn load(filename: &str) -> Result<Tokenizer, W2vError> {
+        let mut tokenizer = Tokenizer::default();
+        for line in BufReader::new(File::open(filename)?).lines() {
+            let line = line?;
+            let invalid = || W2vError::InvalidInput(format!("bad tokenizer setting {}", line));
+            let (name, value) = line.split_once('=').ok_or_else(invalid)?;
+            let flag = || value.parse::<bool>().map_err(|_| invalid());
+            match name {
+                "lowercase" => tokenizer.lowercase = flag()?,
+                "nfkc" => tokenizer.nfkc = flag()?,
+                "punctuation" => {
+                    tokenizer.punctuation = Punctuation::parse(value).ok_or_else(invalid)?
+                }
+                "unicode_words" => tokenizer.unicode_words = flag()?,
+                "numbers" => tokenizer.numbers = flag()?,
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(tokenizer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with(f: impl FnOnce(&mut Tokenizer)) -> Tokenizer {
+        let mut tokenizer = Tokenizer::default();
+        f(&mut tokenizer);
+        tokenizer
+    }
+
+    #[test]
+    fn default_splits_whitespace() {
+        let tokens = Tokenizer::default().tokens(" The cat,\tsat\n");
+        assert_eq!(tokens, ["The", "cat,", "sat"]);
+    }
+
+    #[test]
+    fn lowercase_and_nfkc() {
+        let tokenizer = with(|t| {
+            t.lowercase = true;
+            t.nfkc = true
+        });
+        assert_eq!(tokenizer.tokens("Ｃａｆé ﬁne"), ["café", "fine"]);
+    }
+
+    #[test]
+    fn punctuation() {
+        let text = "\"Hello,\" she said... (really)";
+        let strip = with(|t| t.punctuation = Punctuation::Strip);
+        assert_eq!(strip.tokens(text), ["Hello", "she", "said", "really"]);
+        let split = with(|t| t.punctuation = Punctuation::Split);
+        assert_eq!(
+            split.tokens(text),
+            ["\"", "Hello", ",", "\"", "she", "said", ".", ".", ".", "(", "really", ")"]
+        );
+        // a word of punctuation only is dropped, not emitted as an empty token
+        assert_eq!(strip.tokens("a -- b"), ["a", "b"]);
+    }
+
+    #[test]
+    fn unicode_words() {
+        let tokenizer = with(|t| t.unicode_words = true);
+        assert_eq!(tokenizer.tokens("don't stop."), ["don't", "stop", "."]);
+        let strip = with(|t| {
+            t.unicode_words = true;
+            t.punctuation = Punctuation::Strip
+        });
+        assert_eq!(strip.tokens("don't stop."), ["don't", "stop"]);
+    }
+
+    #[test]
+    fn numbers() {
+        let tokenizer = with(|t| t.numbers = true);
+        assert_eq!(
+            tokenizer.tokens("1,000 -2.5 v2 x"),
+            [NUMBER_TOKEN, NUMBER_TOKEN, "v2", "x"]
+        );
+    }
+
+    #[test]
+    fn query_words() {
+        assert_eq!(Tokenizer::default().word("Paris,"), "Paris,");
+        let tokenizer = with(|t| {
+            t.lowercase = true;
+            t.nfkc = true;
+            t.punctuation = Punctuation::Strip;
+            t.numbers = true
+        });
+        assert_eq!(tokenizer.word("Ｐａｒｉｓ,"), "paris");
+        assert_eq!(tokenizer.word("1999"), NUMBER_TOKEN);
+        assert_eq!(tokenizer.word("New York"), "new york");
+    }
+
+    #[test]
+    fn save_and_load() {
+        let tokenizer = with(|t| {
+            t.lowercase = true;
+            t.punctuation = Punctuation::Split;
+            t.numbers = true
+        });
+        let path = std::env::temp_dir().join(format!("tokenizer-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        tokenizer.save(path).unwrap();
+        let loaded = Tokenizer::load(path);
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(loaded.unwrap(), tokenizer);
+    }
+}
@@ -1,20 +1,141 @@
+use crate::corpus::{record_error, InputFormat, Record};
+use crate::corpus_cache::{CorpusCache, SENTENCE_END};
+use crate::sampler::{seeded_rng, AliasTable};
+use crate::events::{Event, Observer};
+use crate::metrics::{self, MetricsLog};
+use crate::model::LossSum;
+use crate::validation::{Scores, Validation};
 use crate::W2vError;
 use crate::Word2vec;
-use crate::{Argument, Dict, Matrix, Model};
+use crate::{Argument, ArgumentError, Dict, Matrix, Model};
 use parquet::file::reader::FileReader;
 use crate::file_utils::{get_parquet_file_slice_reader};
 use rand::distributions::{IndependentSample, Range};
-use rand::StdRng;
-use std::fs::{metadata, File};
-use std::io::{stdout, BufRead, BufReader, Lines, Read, Seek, SeekFrom, Write};
-use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
-use std::sync::Arc;
+use rand::{thread_rng, Rng, XorShiftRng};
+use std::env::temp_dir;
+use std::fs::{remove_file, File, OpenOptions};
+use std::io::{self, stdin, BufRead, BufReader, Write};
+use std::mem;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
 use std::thread;
 use time::Instant;
-static ALL_WORDS: AtomicUsize = ATOMIC_USIZE_INIT;
-use crate::file_utils::TakeBufStrReader;
+use crate::file_utils::{get_file_range_reader, TeeReader};
 
-fn skipgram(model: &mut Model, line: &Vec<usize>, rng: &mut StdRng, unifrom: &Range<isize>) {
+// input name that makes `train` read the corpus from stdin
+pub const STDIN_INPUT: &str = "-";
+// records per message and messages buffered per worker when streaming
+const QUEUE_BATCH_RECORDS: usize = 1000;
+const QUEUE_DEPTH: usize = 16;
+
+enum Chunk {
+    Records(Vec<Record>),
+    EndEpoch,
+}
+
+// a new file of the temporary directory, removed when dropped, also when
+// training fails or panics
+struct TempFile(String);
+impl TempFile {
+    fn new(prefix: &str) -> io::Result<TempFile> {
+        // unique between the runs of one process and between processes
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        loop {
+            let n = NEXT.fetch_add(1, Ordering::Relaxed);
+            let path = temp_dir().join(format!("{}-{}-{}.txt", prefix, process::id(), n));
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(TempFile(path.to_string_lossy().into_owned())),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = remove_file(&self.0);
+    }
+}
+
+// where a worker thread gets its records from: a byte range of the input
+// file, a queue filled by a single reader thread, or a range of ids of the
+// corpus cache
+enum RecordSource {
+    Range(u64, u64),
+    Queue(Receiver<Chunk>),
+    Ids(Arc<CorpusCache>, usize, usize),
+}
+impl RecordSource {
+    // calls `f` with the subsampled word indices, the number of tokens read,
+    // the tag and the weight of every record of one epoch
+    fn for_each_line<F>(
+        &self,
+        dict: &Dict,
+        arg: &Argument,
+        rng: &mut XorShiftRng,
+        mut f: F,
+    ) -> Result<(), W2vError>
+    where
+        F: FnMut(&[usize], usize, Option<usize>, f32),
+    {
+        let mut line = Vec::new();
+        match *self {
+            RecordSource::Ids(ref cache, start, end) => {
+                for sentence in cache.ids()[start..end].split(|&id| id == SENTENCE_END) {
+                    let ntokens = dict.read_ids(sentence, &mut line, rng);
+                    f(&line, ntokens, None, 1.0);
+                    line.clear();
+                }
+            }
+            _ => {
+                for record in self.epoch_records(&arg.input, &arg.format)? {
+                    let record = record?;
+                    let ntokens = dict.read_line(&record.text, &mut line, rng);
+                    let tag = record.tag.as_ref().and_then(|tag| dict.get_tag_idx(tag));
+                    f(&line, ntokens, tag, record.weight);
+                    line.clear();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn epoch_records<'a>(
+        &'a self,
+        filename: &'a str,
+        format: &InputFormat,
+    ) -> Result<Box<dyn Iterator<Item = Result<Record, W2vError>> + 'a>, W2vError> {
+        match *self {
+            RecordSource::Range(start_pos, end_pos) => {
+                let reader = get_file_range_reader(filename, start_pos, end_pos)?;
+                // malformed records were already counted when building the dict
+                Ok(Box::new(
+                    format
+                        .records(reader, start_pos == 0)?
+                        .filter_map(move |record| {
+                            let record = record.map_err(|err| record_error(err, filename, start_pos));
+                            record.transpose()
+                        }),
+                ))
+            }
+            RecordSource::Queue(ref receiver) => Ok(Box::new(
+                receiver
+                    .iter()
+                    .take_while(|chunk| matches!(chunk, Chunk::Records(_)))
+                    .flat_map(|chunk| match chunk {
+                        Chunk::Records(records) => records,
+                        Chunk::EndEpoch => Vec::new(),
+                    })
+                    .map(Ok),
+            )),
+            RecordSource::Ids(..) => Err(W2vError::RuntimeError),
+        }
+    }
+}
+
+fn skipgram(model: &mut Model, line: &[usize], rng: &mut XorShiftRng, unifrom: &Range<isize>) {
     let length = line.len() as i32;
     for w in 0..length {
         let bound = unifrom.ind_sample(rng) as i32;
@@ -25,166 +146,714 @@ fn skipgram(model: &mut Model, line: &Vec<usize>, rng: &mut StdRng, unifrom: &Ra
         }
     }
 }
-fn print_progress(model: &Model, progress: f32, words: f32, start_time: &Instant) {
-    print!(
-        "\rProgress:{:.1}% words/sec:{:<7.0} lr:{:.4} loss:{:.5}",
-        progress * 100.,
-        ((words * 1.) / (start_time.elapsed().whole_seconds()) as f32) as u64,
-        model.get_lr(),
-        model.get_loss()
-    );
-    stdout().flush().unwrap();
+// trains one record, with its tag as a document vector predicting each word
+fn train_record(
+    model: &mut Model,
+    line: &[usize],
+    tag: Option<usize>,
+    weight: f32,
+    rng: &mut XorShiftRng,
+    unifrom: &Range<isize>,
+) {
+    let lr = model.get_lr();
+    model.set_lr(lr * weight);
+    skipgram(model, line, rng, unifrom);
+    if let Some(tag) = tag {
+        for &w in line {
+            model.update(tag, w);
+        }
+    }
+    model.set_lr(lr);
+}
+// what the threads of one training run share
+struct Shared {
+    dict: Dict,
+    neg_table: Arc<AliasTable>,
+    // tokens trained by all threads, driving the learning rate
+    all_words: AtomicUsize,
+    epoch_tokens: usize,
+    observer: Arc<dyn Observer>,
+    metrics: Option<MetricsLog>,
+    validation: Option<Validation>,
+    // loss of all threads since the last Progress and EpochEnd
+    interval_loss: Mutex<LossSum>,
+    epoch_loss: Mutex<LossSum>,
+    start_time: Instant,
 }
 
-fn get_text_file_line_reader(
-    filename: String,
-    start_pos: u64,
-    end_pos: u64,
-) -> Result<TakeBufStrReader, std::io::Error> {
-    let input_file = File::open(filename)?;
-    let mut reader = BufReader::with_capacity(10000, input_file);
-    reader.seek(SeekFrom::Start(start_pos))?;
-    let handle = reader.take(end_pos - start_pos);
-    Ok(TakeBufStrReader::from_handle(handle))
+impl Shared {
+    fn add_loss(&self, loss: LossSum) {
+        self.interval_loss.lock().unwrap().add(loss);
+        self.epoch_loss.lock().unwrap().add(loss);
+    }
+
+    fn take_loss(sum: &Mutex<LossSum>) -> f64 {
+        mem::take(&mut *sum.lock().unwrap()).mean()
+    }
+
+    fn words_per_sec(&self, words: usize) -> f32 {
+        words as f32 / self.start_time.elapsed().as_seconds_f32().max(1e-3)
+    }
+
+    fn record(
+        &self,
+        kind: &'static str,
+        epoch: u32,
+        words: usize,
+        lr: f32,
+        loss: f64,
+        valid: Option<Scores>,
+    ) -> Result<(), W2vError> {
+        match self.metrics {
+            Some(ref log) => log.write(&metrics::Record {
+                kind,
+                epoch,
+                words,
+                words_per_sec: self.words_per_sec(words),
+                lr,
+                loss,
+                seconds: self.start_time.elapsed().as_seconds_f64(),
+                valid,
+            }),
+            None => Ok(()),
+        }
+    }
 }
 
 /*
 pass part of file to a thread and run training
 */
 fn train_thread(
-    dict: &Dict,
-    mut input: &mut Matrix,
-    mut output: &mut Matrix,
+    shared: &Shared,
+    input: &mut Matrix,
+    output: &mut Matrix,
     arg: Argument,
     tid: u32,
-    neg_table: Arc<Vec<usize>>,
-    start_pos: u64,
-    end_pos: u64,
+    source: RecordSource,
 ) -> Result<bool, W2vError> {
+    let (dict, all_words) = (&shared.dict, &shared.all_words);
     let between = Range::new(1, (arg.win + 1) as isize);
-    let mut rng = StdRng::new().unwrap();
-    let mut model = Model::new(&mut input, &mut output, arg.dim, arg.lr, arg.neg, neg_table);
-    let start_time = Instant::now();
-    let mut line: Vec<usize> = Vec::new();
+    // stream 0 initializes the vectors, each thread gets three more
+    let seed = arg.seed.unwrap_or(0);
+    let stream = 3 * tid as u64;
+    let (mut rng, mut sample_rng) = (seeded_rng(seed, stream + 1), seeded_rng(seed, stream + 2));
+    let mut model = Model::new(
+        input,
+        output,
+        arg.dim,
+        arg.lr_schedule.lr(&arg, 0.),
+        arg.neg,
+        shared.neg_table.clone(),
+        seeded_rng(seed, stream + 3),
+    );
     let (mut token_count, mut epoch) = (0, 0);
-    let all_tokens = arg.epoch as usize * dict.ntokens;
-    println!("all tokens {}", all_tokens);
+    let all_tokens = arg.epoch as usize * shared.epoch_tokens;
+    // a failed metrics write, returned at the end of the epoch so that with
+    // evaluated epochs the threads still stop together
+    let mut error = Ok(());
     while epoch < arg.epoch {
-        let take_buf_line_reader =
-            get_text_file_line_reader(arg.input.clone(), start_pos, end_pos)?;
-        for mut line_buffer in take_buf_line_reader.into_iter() {
-            token_count += dict.read_line(&mut line_buffer, &mut line);
-            line_buffer.clear();
-            skipgram(&mut model, &line, &mut rng, &between);
-            line.clear();
+        let read = source.for_each_line(dict, &arg, &mut sample_rng, |line, ntokens, tag, weight| {
+            token_count += ntokens;
+            train_record(&mut model, line, tag, weight, &mut rng, &between);
             if token_count > arg.lr_update as usize {
-                let words = ALL_WORDS.fetch_add(token_count, Ordering::SeqCst) as f32;
-                let progress = words / all_tokens as f32;
-                model.set_lr(arg.lr * (1.0 - progress));
+                let words = all_words.fetch_add(token_count, Ordering::SeqCst) + token_count;
+                let progress = words as f32 / all_tokens as f32;
+                model.set_lr(arg.lr_schedule.lr(&arg, progress));
                 token_count = 0;
+                shared.add_loss(model.take_loss());
                 if tid == 0 {
-                    //if arg.verbose {
-                    print_progress(&model, progress, words, &start_time);
-                    //}
+                    let (lr, loss) = (model.get_lr(), Shared::take_loss(&shared.interval_loss));
+                    shared.observer.on_event(&Event::Progress {
+                        progress,
+                        words,
+                        words_per_sec: shared.words_per_sec(words),
+                        lr,
+                        loss,
+                    });
+                    if error.is_ok() {
+                        error = shared.record("interval", epoch, words, lr, loss, None);
+                    }
                 }
             }
+        });
+        let read = read.and(mem::replace(&mut error, Ok(())));
+        all_words.fetch_add(token_count, Ordering::SeqCst);
+        token_count = 0;
+        shared.add_loss(model.take_loss());
+        // the threads meet here, and stop together, when epochs are evaluated
+        let stop = match shared.validation {
+            Some(ref validation) => {
+                let (input, output) = model.matrices();
+                validation.end_epoch(tid, epoch, input, output, &shared.neg_table, read.is_err())
+            }
+            None => false,
+        };
+        read?;
+        if tid == 0 {
+            // without evaluation other threads may still be in this epoch,
+            // their loss since their last update goes to the next one
+            let loss = Shared::take_loss(&shared.epoch_loss);
+            shared.observer.on_event(&Event::EpochEnd { epoch, loss });
+            let words = all_words.load(Ordering::SeqCst);
+            let scores = shared.validation.as_ref().map(|validation| {
+                let (scores, best_epoch) = validation.last();
+                shared.observer.on_event(&Event::Validated { epoch, scores, best_epoch });
+                if stop {
+                    shared.observer.on_event(&Event::EarlyStopped { epoch, best_epoch });
+                }
+                scores
+            });
+            error = shared.record("epoch", epoch, words, model.get_lr(), loss, scores);
+        }
+        if stop {
+            break;
         }
-        println!("epoch {}", epoch);
         epoch += 1;
     }
-    ALL_WORDS.fetch_add(token_count, Ordering::SeqCst);
-    if tid == 0 && arg.verbose {
+    error.map(|_| true)
+}
+// hands out records of `filename` to the workers in batches, once per epoch.
+// with `from_stdin` the first epoch reads stdin instead, copying it to
+// `filename` for the epochs after it
+fn feed_records(
+    filename: &str,
+    format: &InputFormat,
+    epochs: u32,
+    from_stdin: bool,
+    senders: Vec<SyncSender<Chunk>>,
+) -> Result<(), W2vError> {
+    let stdin = stdin();
+    let mut next = 0;
+    for epoch in 0..epochs {
+        let reader: Box<dyn BufRead> = if from_stdin && epoch == 0 {
+            let copy: Box<dyn Write> = if epochs > 1 {
+                Box::new(File::create(filename)?)
+            } else {
+                Box::new(io::sink())
+            };
+            Box::new(BufReader::with_capacity(
+                10000,
+                TeeReader::new(stdin.lock(), copy),
+            ))
+        } else {
+            Box::new(BufReader::with_capacity(10000, File::open(filename)?))
+        };
+        let mut records = format.records(reader, true)?;
+        let mut batch = Vec::with_capacity(QUEUE_BATCH_RECORDS);
         loop {
-            let words = ALL_WORDS.load(Ordering::SeqCst);
-            let progress = words as f32 / all_tokens as f32;
-            print_progress(&model, progress, words as f32, &start_time);
-            if words >= all_tokens {
-                assert_eq!(words, all_tokens);
-                print_progress(&model, progress, words as f32, &start_time);
-                println!(
-                    "\ntotal train time:{} s",
-                    start_time.elapsed().whole_seconds()
-                );
+            let record = records
+                .next()
+                .transpose()
+                .map_err(|err| record_error(err, filename, 0))?;
+            let eof = record.is_none();
+            if let Some(Some(record)) = record {
+                batch.push(record);
+            }
+            if batch.len() == QUEUE_BATCH_RECORDS || (eof && !batch.is_empty()) {
+                let chunk = Chunk::Records(mem::replace(
+                    &mut batch,
+                    Vec::with_capacity(QUEUE_BATCH_RECORDS),
+                ));
+                match send_to_any(&senders, next, chunk) {
+                    Some(i) => next = (i + 1) % senders.len(),
+                    // a worker stopped early, its error is reported when joined
+                    None => return Ok(()),
+                }
+            }
+            if eof {
                 break;
             }
         }
+        for sender in &senders {
+            if sender.send(Chunk::EndEpoch).is_err() {
+                return Ok(());
+            }
+        }
     }
-    Ok(true)
+    Ok(())
 }
-fn file_split_indices(filename: &str, n_split: u64) -> Result<Vec<u64>, W2vError> {
-    let all_tokens = metadata(filename)?.len();
-    let input_file = File::open(filename)?;
-    let mut reader = BufReader::with_capacity(1000, input_file);
-    let offset = all_tokens / n_split;
-    let mut junk = Vec::new();
-    let mut bytes = Vec::new();
-    bytes.push(0);
-    for i in 1..n_split {
-        reader.seek(SeekFrom::Start(offset * i)).unwrap();
-        let extra = reader.read_until(b'\n', &mut junk)?;
-        bytes.push(offset * i + extra as u64);
+
+// gives the chunk to the first worker with room in its queue, starting at
+// `first`, and waits on `first` when all of them are busy
+fn send_to_any(senders: &[SyncSender<Chunk>], first: usize, chunk: Chunk) -> Option<usize> {
+    let mut chunk = chunk;
+    for k in 0..senders.len() {
+        let i = (first + k) % senders.len();
+        match senders[i].try_send(chunk) {
+            Ok(()) => return Some(i),
+            Err(TrySendError::Full(c)) => chunk = c,
+            Err(TrySendError::Disconnected(_)) => return None,
+        }
     }
-    bytes.push(all_tokens);
-    Ok(bytes)
+    senders[first].send(chunk).ok().map(|_| first)
+}
+
+// counts the words on stdin while copying it to a temporary file that the
+// training epochs are then read from
+fn dict_from_stdin(
+    args: &Argument,
+    cache: &str,
+    observer: &dyn Observer,
+) -> Result<Dict, W2vError> {
+    let stdin = stdin();
+    let tee = TeeReader::new(stdin.lock(), File::create(cache)?);
+    Dict::new_from_reader(BufReader::with_capacity(10000, tee), args, observer)
+}
+
+// counts the words of the input, a file or stdin, and saves the vocabulary
+// to the output file
+pub fn build_vocab(args: &Argument) -> Result<(), W2vError> {
+    build_vocab_with_observer(args, Arc::new(()))
 }
 
+pub fn build_vocab_with_observer(
+    args: &Argument,
+    observer: Arc<dyn Observer>,
+) -> Result<(), W2vError> {
+    let dict = if args.input == STDIN_INPUT {
+        Dict::new_from_reader(stdin().lock(), args, &*observer)?
+    } else {
+        Dict::new_from_file(&args.input, args, &*observer)?
+    };
+    dict.save_vocab(&args.output)
+}
+
+// trains without reporting anything
 pub fn train(args: &Argument) -> Result<Word2vec, W2vError> {
-    let dict = Dict::new_from_file(
-        &args.input,
-        args.min_count,
-        args.threshold,
-        args.verbose,
-        args.max_rows,
-    )?;
-
-    let dict = Arc::new(dict);
+    train_with_observer(args, Arc::new(()))
+}
+
+pub fn train_sentences<I, S, W>(args: &Argument, sentences: I) -> Result<Word2vec, W2vError>
+where
+    I: IntoIterator<Item = S> + Clone,
+    S: AsRef<[W]>,
+    W: AsRef<str>,
+{
+    train_sentences_with_observer(args, sentences, Arc::new(()))
+}
+
+/// Trains word vectors and returns them, without saving anything to
+/// `Argument::output`.
+pub struct Trainer {
+    args: Argument,
+    observer: Arc<dyn Observer>,
+}
+
+impl Trainer {
+    /// checks `args` like `ArgumentBuilder::build`
+    pub fn new(args: Argument) -> Result<Trainer, ArgumentError> {
+        args.validate()?;
+        Ok(Trainer {
+            args,
+            observer: Arc::new(()),
+        })
+    }
+
+    /// receives the progress of counting and training, nothing by default
+    pub fn observer(mut self, observer: Arc<dyn Observer>) -> Trainer {
+        self.observer = observer;
+        self
+    }
+
+    pub fn args(&self) -> &Argument {
+        &self.args
+    }
+
+    pub fn train(&self) -> Result<Word2vec, W2vError> {
+        train_with_observer(&self.args, self.observer.clone())
+    }
+
+    /// trains from sentences the caller already tokenized, like
+    /// `vec![vec!["a", "b"]].iter()` or `&sentences` with
+    /// `sentences: Vec<Vec<String>>`, instead of reading `Argument::input`.
+    ///
+    /// `sentences` is iterated twice: once to count the words, unless
+    /// `Argument::read_vocab` is set, and once to encode them into word ids
+    /// kept in memory for every epoch. The tokenizer options do not apply to
+    /// them, but are saved with the model for looking words up.
+    pub fn train_sentences<I, S, W>(&self, sentences: I) -> Result<Word2vec, W2vError>
+    where
+        I: IntoIterator<Item = S> + Clone,
+        S: AsRef<[W]>,
+        W: AsRef<str>,
+    {
+        train_sentences_with_observer(&self.args, sentences, self.observer.clone())
+    }
+}
+
+// trains, sending the progress of counting and training to `observer`
+pub fn train_with_observer(
+    args: &Argument,
+    observer: Arc<dyn Observer>,
+) -> Result<Word2vec, W2vError> {
+    let args = &run_arguments(args, true)?;
+    if args.input != STDIN_INPUT {
+        let dict = match args.read_vocab {
+            Some(ref vocab) => Dict::load_vocab(vocab, args, &*observer)?,
+            None => Dict::new_from_file(&args.input, args, &*observer)?,
+        };
+        // workers reading byte ranges past the header need column indices
+        let mut args = args.clone();
+        args.format = args.format.resolve_columns(&args.input)?;
+        if let Some(ref path) = args.corpus_cache {
+            let input = BufReader::with_capacity(10000, File::open(&args.input)?);
+            return train_from_cache(&args, dict, path, input, observer);
+        }
+        let splits = args.format.split_indices(&args.input, args.nthreads as u64)?;
+        let sources = (0..args.nthreads as usize)
+            .map(|i| RecordSource::Range(splits[i], splits[i + 1]))
+            .collect();
+        let epoch_tokens = dict.ntokens;
+        return train_with_sources(&args, dict, sources, None, epoch_tokens, observer);
+    }
+    let cache = TempFile::new("word2vec-stdin")?;
+    train_from_stdin(args, &cache.0, observer)
+}
+
+// the arguments of one run, checked, with the seed and thread count it
+// trains with. `reads_input` tells whether it trains from Argument::input
+fn run_arguments(args: &Argument, reads_input: bool) -> Result<Argument, W2vError> {
+    let invalid = |err: ArgumentError| W2vError::InvalidInput(err.to_string());
+    args.validate().map_err(invalid)?;
+    if reads_input && args.input.is_empty() {
+        return Err(invalid(ArgumentError::Invalid("input", "is empty".to_string())));
+    }
+    let mut args = args.clone();
+    // a run without a seed gets a random one, reported to reproduce it
+    args.seed.get_or_insert_with(|| thread_rng().gen());
+    // hogwild updates from several threads race, one thread does not
+    if args.deterministic {
+        args.nthreads = 1;
+    }
+    Ok(args)
+}
+
+// trains from sentences already tokenized in memory instead of
+// `Argument::input`, which is not read. they are read twice, once to count
+// the words and once to encode them into ids every epoch trains from, or
+// once with `Argument::read_vocab`
+pub fn train_sentences_with_observer<I, S, W>(
+    args: &Argument,
+    sentences: I,
+    observer: Arc<dyn Observer>,
+) -> Result<Word2vec, W2vError>
+where
+    I: IntoIterator<Item = S> + Clone,
+    S: AsRef<[W]>,
+    W: AsRef<str>,
+{
+    let args = &run_arguments(args, false)?;
+    let dict = match args.read_vocab {
+        Some(ref vocab) => Dict::load_vocab(vocab, args, &*observer)?,
+        None => Dict::new_from_sentences(sentences.clone(), args, &*observer),
+    };
+    let cache = Arc::new(CorpusCache::from_sentences(sentences, &dict));
+    train_from_ids(args, dict, cache, observer)
+}
+
+// with a saved vocabulary stdin is trained from as it arrives, otherwise it
+// is counted into `cache` first
+fn train_from_stdin(
+    args: &Argument,
+    cache: &str,
+    observer: Arc<dyn Observer>,
+) -> Result<Word2vec, W2vError> {
+    let (dict, from_stdin) = match args.read_vocab {
+        Some(ref vocab) => (Dict::load_vocab(vocab, args, &*observer)?, true),
+        None => (dict_from_stdin(args, cache, &*observer)?, false),
+    };
+    if let Some(ref path) = args.corpus_cache {
+        return if from_stdin {
+            train_from_cache(args, dict, path, stdin().lock(), observer)
+        } else {
+            let input = BufReader::with_capacity(10000, File::open(cache)?);
+            train_from_cache(args, dict, path, input, observer)
+        };
+    }
+    let (senders, sources) = (0..args.nthreads)
+        .map(|_| {
+            let (sender, receiver) = sync_channel(QUEUE_DEPTH);
+            (sender, RecordSource::Queue(receiver))
+        })
+        .unzip();
+    let (filename, format, epochs) = (cache.to_string(), args.format.clone(), args.epoch);
+    let feeder =
+        thread::spawn(move || feed_records(&filename, &format, epochs, from_stdin, senders));
+    let epoch_tokens = dict.ntokens;
+    train_with_sources(args, dict, sources, Some(feeder), epoch_tokens, observer)
+}
+
+// encodes `input` into the corpus cache once and trains every epoch from it
+fn train_from_cache<R: BufRead>(
+    args: &Argument,
+    dict: Dict,
+    path: &str,
+    input: R,
+    observer: Arc<dyn Observer>,
+) -> Result<Word2vec, W2vError> {
+    let cache = Arc::new(CorpusCache::create(path, input, &args.format, &dict)?);
+    train_from_ids(args, dict, cache, observer)
+}
+
+// splits the encoded corpus between the threads by whole sentences
+fn train_from_ids(
+    args: &Argument,
+    dict: Dict,
+    cache: Arc<CorpusCache>,
+    observer: Arc<dyn Observer>,
+) -> Result<Word2vec, W2vError> {
+    let sources = cache
+        .split_indices(args.nthreads as usize)
+        .windows(2)
+        .map(|w| RecordSource::Ids(cache.clone(), w[0], w[1]))
+        .collect();
+    let epoch_tokens = cache.ntokens();
+    train_with_sources(args, dict, sources, None, epoch_tokens, observer)
+}
+
+fn train_with_sources(
+    args: &Argument,
+    dict: Dict,
+    sources: Vec<RecordSource>,
+    feeder: Option<thread::JoinHandle<Result<(), W2vError>>>,
+    epoch_tokens: usize,
+    observer: Arc<dyn Observer>,
+) -> Result<Word2vec, W2vError> {
     let mut input_mat = Matrix::new(dict.nsize(), args.dim);
     let mut output_mat = Matrix::new(dict.nsize(), args.dim);
 
-    input_mat.unifrom(1.0f32 / args.dim as f32);
+    input_mat.unifrom(1.0f32 / args.dim as f32, &mut seeded_rng(args.seed.unwrap_or(0), 0));
     output_mat.zero();
     let input = Arc::new(input_mat.make_send());
     let output = Arc::new(output_mat.make_send());
-    let neg_table = dict.init_negative_table();
-    let splits = file_split_indices(&args.input, args.nthreads as u64)?;
+    observer.on_event(&Event::TrainStarted {
+        seed: args.seed.unwrap_or(0),
+        threads: sources.len() as u32,
+        tokens: args.epoch as usize * epoch_tokens,
+    });
+    let shared = Arc::new(Shared {
+        neg_table: dict.negative_sampler(args)?,
+        validation: Validation::new(args, &dict, sources.len())?,
+        dict,
+        all_words: AtomicUsize::new(0),
+        epoch_tokens,
+        observer,
+        metrics: args.metrics.as_deref().map(MetricsLog::create).transpose()?,
+        interval_loss: Mutex::new(LossSum::default()),
+        epoch_loss: Mutex::new(LossSum::default()),
+        start_time: Instant::now(),
+    });
     let mut handles = Vec::new();
-    for i in 0..args.nthreads {
-        let (input, output, dict, arg, neg_table) = (
-            input.clone(),
-            output.clone(),
-            dict.clone(),
-            args.clone(),
-            neg_table.clone(),
-        );
-        let splits = splits.clone();
+    for (i, source) in sources.into_iter().enumerate() {
+        let (input, output, shared, arg) =
+            (input.clone(), output.clone(), shared.clone(), args.clone());
         handles.push(thread::spawn(move || {
-            let dict: &Dict = dict.as_ref();
             let input = input.as_ref().inner.get();
             let output = output.as_ref().inner.get();
             train_thread(
-                &dict,
+                &shared,
                 unsafe { &mut *input },
                 unsafe { &mut *output },
                 arg,
-                i,
-                neg_table,
-                splits[i as usize],
-                splits[(i + 1) as usize],
+                i as u32,
+                source,
             )
         }));
     }
+    // every thread is joined before returning the first error, none is left
+    // writing to the matrices. a panic is an error too
+    let mut result = Ok(());
     for h in handles {
-        h.join().unwrap()?;
+        let joined = h.join().unwrap_or(Err(W2vError::RuntimeError));
+        result = result.and(joined.map(|_| ()));
+    }
+    if let Some(feeder) = feeder {
+        result = result.and(feeder.join().unwrap_or(Err(W2vError::RuntimeError)));
     }
+    result?;
 
-    let input = Arc::try_unwrap(input).unwrap();
-    let output = Arc::try_unwrap(output).unwrap();
-    let dict = Arc::try_unwrap(dict).unwrap();
-    let w2v = Word2vec::new(
-        unsafe { input.inner.into_inner() },
-        unsafe { output.inner.into_inner() },
-        args.dim,
-        dict,
-    );
+    let shared = match Arc::try_unwrap(shared) {
+        Ok(shared) => shared,
+        Err(_) => unreachable!("training threads are joined"),
+    };
+    shared.observer.on_event(&Event::Finished {
+        seconds: shared.start_time.elapsed().as_seconds_f64(),
+    });
+    // the vectors of the best epoch when epochs were evaluated
+    let (input, output) = match shared.validation.and_then(Validation::into_best) {
+        Some(best) => best,
+        None => (
+            Arc::try_unwrap(input).unwrap().inner.into_inner(),
+            Arc::try_unwrap(output).unwrap().inner.into_inner(),
+        ),
+    };
+    let mut w2v = Word2vec::new(input, output, args.dim, shared.dict);
+    w2v.set_oov(args.oov);
     Ok(w2v)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ArgumentBuilder, Column, Command, CsvFormat};
+    use std::fs;
+
+    fn corpus(lines: usize) -> TempFile {
+        let file = TempFile::new("corpus").unwrap();
+        let mut rng = seeded_rng(9, 0);
+        let text: String = (0..lines)
+            .map(|_| {
+                let words: Vec<String> = (0..12)
+                    .map(|_| format!("w{}", rng.gen_range(0, 40)))
+                    .collect();
+                words.join(" ") + "\n"
+            })
+            .collect();
+        fs::write(&file.0, text).unwrap();
+        file
+    }
+
+    #[test]
+    fn same_seed_same_vectors() {
+        let corpus = corpus(300);
+        let run = |seed: u64| {
+            let args = ArgumentBuilder::new(corpus.0.clone(), Command::Train)
+                .format(InputFormat::Text)
+                .min_count(1)
+                .threads(4)
+                .deterministic(true)
+                .seed(Some(seed))
+                .epoch(2)
+                .dim(10)
+                .build()
+                .unwrap();
+            let bits = |v: &[f32]| v.iter().map(|x| x.to_bits()).collect::<Vec<u32>>();
+            bits(train(&args).unwrap().vectors())
+        };
+        let first = run(5);
+        assert_eq!(first, run(5));
+        assert_ne!(first, run(6));
+
+        // and from sentences in memory
+        let text = fs::read_to_string(&corpus.0).unwrap();
+        let sentences: Vec<Vec<&str>> = text.lines().map(|l| l.split(' ').collect()).collect();
+        let args = ArgumentBuilder::new(String::new(), Command::Train)
+            .min_count(1)
+            .deterministic(true)
+            .seed(Some(5))
+            .dim(10)
+            .build()
+            .unwrap();
+        let vectors = || {
+            train_sentences(&args, &sentences)
+                .unwrap()
+                .vectors()
+                .to_vec()
+        };
+        assert_eq!(vectors(), vectors());
+    }
+
+    #[test]
+    fn a_failing_thread_fails_training() {
+        let corpus = TempFile::new("corpus").unwrap();
+        let vocab = TempFile::new("vocab").unwrap();
+        let mut csv = String::from("text,weight\n");
+        for i in 0..400 {
+            let weight = if i == 300 { "-1" } else { "1" };
+            csv += &format!("a b c d e f,{}\n", weight);
+        }
+        fs::write(&corpus.0, csv).unwrap();
+        let words: String = "abcdef".chars().map(|w| format!("{}\t400\n", w)).collect();
+        fs::write(&vocab.0, format!("#word2vec-vocab\t2400\n{}", words)).unwrap();
+        // the vocabulary is read, so the bad weight is only found by a
+        // training thread
+        let format = CsvFormat {
+            weight: Some(Column::Name("weight".to_string())),
+            ..CsvFormat::default()
+        };
+        let args = ArgumentBuilder::new(corpus.0.clone(), Command::Train)
+            .format(InputFormat::Csv(format))
+            .output("model".to_string())
+            .read_vocab(Some(vocab.0.clone()))
+            .min_count(1)
+            .threads(2)
+            .epoch(1)
+            .dim(8)
+            .build()
+            .unwrap();
+        match train(&args) {
+            Err(W2vError::InvalidInput(message)) => {
+                assert!(message.contains("line 302"), "{}", message)
+            }
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+    }
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<Event>>);
+    impl Observer for Recorder {
+        fn on_event(&self, event: &Event) {
+            self.0.lock().unwrap().push(event.clone());
+        }
+    }
+
+    #[test]
+    fn observers_see_events_in_order() {
+        let corpus = corpus(300);
+        let similarity = TempFile::new("similarity").unwrap();
+        fs::write(&similarity.0, "w0 w1 1\nw2 w3 2\nw4 w5 3\n").unwrap();
+        let args = ArgumentBuilder::new(corpus.0.clone(), Command::Train)
+            .format(InputFormat::Text)
+            .min_count(1)
+            .deterministic(true)
+            .seed(Some(1))
+            .epoch(3)
+            .dim(10)
+            .lr_update(500)
+            .valid_similarity(Some(similarity.0.clone()))
+            .build()
+            .unwrap();
+        let recorder = Arc::new(Recorder::default());
+        train_with_observer(&args, recorder.clone()).unwrap();
+        let events = recorder.0.lock().unwrap();
+
+        let mut names: Vec<&str> = events
+            .iter()
+            .map(|event| match *event {
+                Event::WordsRead(_) => "WordsRead",
+                Event::VocabBuilt { .. } => "VocabBuilt",
+                Event::TrainStarted { .. } => "TrainStarted",
+                Event::Progress { .. } => "Progress",
+                Event::EpochEnd { .. } => "EpochEnd",
+                Event::Validated { .. } => "Validated",
+                Event::EarlyStopped { .. } => "EarlyStopped",
+                Event::Finished { .. } => "Finished",
+            })
+            .collect();
+        names.dedup();
+        let mut expected = vec!["VocabBuilt", "TrainStarted"];
+        for _ in 0..3 {
+            expected.extend(["Progress", "EpochEnd", "Validated"]);
+        }
+        expected.push("Finished");
+        assert_eq!(names, expected);
+
+        let ended: Vec<u32> = events
+            .iter()
+            .filter_map(|event| match *event {
+                Event::EpochEnd { epoch, .. } | Event::Validated { epoch, .. } => Some(epoch),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ended, [0, 0, 1, 1, 2, 2]);
+        let progress: Vec<f32> = events
+            .iter()
+            .filter_map(|event| match *event {
+                Event::Progress { progress, .. } => Some(progress),
+                _ => None,
+            })
+            .collect();
+        assert!(progress.windows(2).all(|w| w[0] < w[1]), "{:?}", progress);
+        assert!(progress.iter().all(|&p| p > 0. && p <= 1.));
+    }
+}
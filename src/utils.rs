@@ -1,4 +1,11 @@
-use clap;
+use crate::config::config_args;
+use crate::corpus::{Column, CsvFormat, InputFormat};
+use crate::dictionary::Oov;
+use crate::hnsw::{HnswParams, MAX_M};
+use crate::lr_schedule::LrSchedule;
+use crate::validation::ValidMetric;
+use crate::tokenizer::{Punctuation, Tokenizer};
+use clap::{self, Arg, ArgMatches};
 use parquet::errors::ParquetError;
 use std::error;
 use std::fmt;
@@ -8,13 +15,21 @@ use std::num;
 pub enum W2vError {
     File(io::Error),
     ParquetFile(ParquetError),
+    InvalidInput(String),
     RuntimeError,
+    // a query for a word the model has no vector for
+    OutOfVocabulary(String),
 }
 impl From<io::Error> for W2vError {
     fn from(err: io::Error) -> W2vError {
         W2vError::File(err)
     }
 }
+impl From<csv::Error> for W2vError {
+    fn from(err: csv::Error) -> W2vError {
+        W2vError::InvalidInput(err.to_string())
+    }
+}
 impl From<ParquetError> for W2vError {
     fn from(err: ParquetError) -> W2vError {
         W2vError::ParquetFile(err)
@@ -26,7 +41,9 @@ impl fmt::Display for W2vError {
         match *self {
             W2vError::ParquetFile(ref reason) => write!(f, "open file error:{}", reason),
             W2vError::File(ref reason) => write!(f, "open file error:{}", reason),
+            W2vError::InvalidInput(ref reason) => write!(f, "invalid input:{}", reason),
             W2vError::RuntimeError => write!(f, "word2vec runtime error"),
+            W2vError::OutOfVocabulary(ref word) => write!(f, "out of vocabulary word:{}", word),
         }
     }
 }
@@ -35,7 +52,9 @@ impl error::Error for W2vError {
     fn description(&self) -> &str {
         match *self {
             W2vError::File(ref err) => err.description(),
+            W2vError::InvalidInput(ref reason) => reason,
             W2vError::RuntimeError => "RuntimeError",
+            W2vError::OutOfVocabulary(_) => "out of vocabulary word",
             W2vError::ParquetFile(ref err) => err.description(),
         }
     }
@@ -44,6 +63,10 @@ pub enum ArgumentError {
     ParseArg(clap::Error),
     ParseInt(num::ParseIntError),
     ParseFloat(num::ParseFloatError),
+    // an unreadable config file
+    Config(String),
+    // an option and what is wrong with its value
+    Invalid(&'static str, String),
 }
 impl From<clap::Error> for ArgumentError {
     fn from(err: clap::Error) -> ArgumentError {
@@ -68,6 +91,8 @@ impl fmt::Display for ArgumentError {
             ArgumentError::ParseArg(ref err) => write!(f, "Parse args:{}", err),
             ArgumentError::ParseInt(ref err) => write!(f, "Parse int:{}", err),
             ArgumentError::ParseFloat(ref err) => write!(f, "Parse float:{}", err),
+            ArgumentError::Config(ref reason) => write!(f, "Config:{}", reason),
+            ArgumentError::Invalid(option, ref reason) => write!(f, "Invalid {}:{}", option, reason),
         }
     }
 }
@@ -77,6 +102,8 @@ impl fmt::Debug for ArgumentError {
             ArgumentError::ParseArg(ref err) => write!(f, "Parse args:{:?}", err),
             ArgumentError::ParseInt(ref err) => write!(f, "Parse int:{:?}", err),
             ArgumentError::ParseFloat(ref err) => write!(f, "Parse float:{:?}", err),
+            ArgumentError::Config(ref reason) => write!(f, "Config:{:?}", reason),
+            ArgumentError::Invalid(option, ref reason) => write!(f, "Invalid {}:{:?}", option, reason),
         }
     }
 }
@@ -86,6 +113,8 @@ impl error::Error for ArgumentError {
             ArgumentError::ParseArg(ref err) => err.description(),
             ArgumentError::ParseFloat(ref err) => err.description(),
             ArgumentError::ParseInt(ref err) => err.description(),
+            ArgumentError::Config(ref reason) => reason,
+            ArgumentError::Invalid(_, ref reason) => reason,
         }
     }
     fn cause(&self) -> Option<&dyn error::Error> {
@@ -93,6 +122,7 @@ impl error::Error for ArgumentError {
             ArgumentError::ParseArg(ref err) => Some(err),
             ArgumentError::ParseFloat(ref err) => Some(err),
             ArgumentError::ParseInt(ref err) => Some(err),
+            ArgumentError::Config(_) | ArgumentError::Invalid(..) => None,
         }
     }
 }
@@ -100,11 +130,18 @@ impl error::Error for ArgumentError {
 pub enum Command {
     Train,
     Test,
+    Vocab,
+    Serve,
+    Index,
 }
 
+// where the serve command listens when no address is given
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:8080";
+
 #[derive(Debug, Clone)]
 pub struct Argument {
     pub input: String,
+    pub format: InputFormat,
     pub output: String,
     pub lr: f32,
     pub dim: usize,
@@ -118,29 +155,110 @@ pub struct Argument {
     pub command: Command,
     pub verbose: bool,
     pub max_rows: Option<usize>,
+    pub corpus_cache: Option<String>,
+    pub tokenizer: Tokenizer,
+    pub max_vocab_size: Option<usize>,
+    pub max_final_vocab: Option<usize>,
+    pub read_vocab: Option<String>,
+    pub oov: Oov,
+    pub neg_pow: f32,
+    pub noise_weights: Option<String>,
+    // seeds every random generator, picked at random when not given
+    pub seed: Option<u64>,
+    // train on a single thread so the same seed gives the same vectors
+    pub deterministic: bool,
+    pub lr_schedule: LrSchedule,
+    // the learning rate never goes below it
+    pub min_lr: f32,
+    // part of training over which the learning rate rises from 0 to lr
+    pub warmup: f32,
+    // factor of the learning rate per epoch for LrSchedule::Step
+    pub lr_decay: f32,
+    // .csv or json lines file of loss, learning rate and speed during training
+    pub metrics: Option<String>,
+    // held-out corpus and benchmarks evaluated at the end of every epoch
+    pub valid: Option<String>,
+    pub valid_similarity: Option<String>,
+    pub valid_analogy: Option<String>,
+    // what the epochs are compared by, the first of the data given by default
+    pub valid_metric: Option<ValidMetric>,
+    // epochs without improvement before training stops
+    pub patience: Option<u32>,
+    // host:port the serve command listens on
+    pub address: String,
+    // the nearest neighbour index the index command builds
+    pub hnsw: HnswParams,
 }
 
-struct ArgumentBuilder {
-    pub input: String,
-    pub output: String,
-    pub lr: f32,
-    pub dim: usize,
-    pub win: usize,
-    pub epoch: u32,
-    pub neg: usize,
-    pub nthreads: u32,
-    pub min_count: u32,
-    pub threshold: f32,
-    pub lr_update: u32,
-    pub command: Command,
-    pub verbose: bool,
-    pub max_rows: Option<usize>,
+impl Argument {
+    /// checks the values that would fail or silently misbehave in training,
+    /// naming the option of the first invalid one. the input is checked by
+    /// the training reading it, training from sentences in memory has none
+    pub fn validate(&self) -> Result<(), ArgumentError> {
+        let check = |ok: bool, option: &'static str, reason: &str| {
+            if ok {
+                Ok(())
+            } else {
+                Err(ArgumentError::Invalid(option, reason.to_string()))
+            }
+        };
+        check(self.dim > 0, "dim", "must be at least 1")?;
+        check(self.win > 0, "win", "must be at least 1")?;
+        check(self.epoch > 0, "epoch", "must be at least 1")?;
+        check(self.neg > 0, "neg", "must be at least 1")?;
+        check(self.nthreads > 0, "thread", "must be at least 1")?;
+        if self.command == Command::Index {
+            let m = (2..=MAX_M).contains(&self.hnsw.m);
+            check(m, "m", &format!("must be between 2 and {}", MAX_M))?;
+            check(self.hnsw.ef_construction > 0, "ef_construction", "must be at least 1")?;
+            check(self.hnsw.ef_search > 0, "ef_search", "must be at least 1")?;
+        }
+        check(self.min_count > 0, "min_count", "must be at least 1")?;
+        check(self.lr_update > 0, "lr_update", "must be at least 1")?;
+        check(self.lr > 0. && self.lr.is_finite(), "lr", "must be positive")?;
+        check(
+            self.min_lr >= 0. && self.min_lr <= self.lr,
+            "min_lr",
+            "must be between 0 and lr",
+        )?;
+        check(
+            (0. ..1.).contains(&self.warmup),
+            "warmup",
+            "must be at least 0 and below 1",
+        )?;
+        check(self.lr_decay > 0. && self.lr_decay.is_finite(), "lr_decay", "must be positive")?;
+        check(self.threshold >= 0. && self.threshold.is_finite(), "threshold", "must not be negative")?;
+        check(self.neg_pow.is_finite(), "neg_pow", "must be a number")?;
+        check(self.max_vocab_size != Some(0), "max_vocab_size", "must be at least 1")?;
+        check(self.max_final_vocab != Some(0), "max_final_vocab", "must be at least 1")?;
+        check(self.patience != Some(0), "patience", "must be at least 1")?;
+        let held_out = [&self.valid, &self.valid_similarity, &self.valid_analogy];
+        check(
+            self.patience.is_none() || held_out.iter().any(|file| file.is_some()),
+            "patience",
+            "needs --valid, --valid_similarity or --valid_analogy",
+        )?;
+        let metric_data = match self.valid_metric {
+            Some(ValidMetric::Loss) => &self.valid,
+            Some(ValidMetric::Similarity) => &self.valid_similarity,
+            Some(ValidMetric::Analogy) => &self.valid_analogy,
+            None => &None,
+        };
+        check(
+            self.valid_metric.is_none() || metric_data.is_some(),
+            "valid_metric",
+            "has no held-out data",
+        )
+    }
 }
-impl ArgumentBuilder {
-    pub fn new(input: String, command: Command) -> ArgumentBuilder {
-        ArgumentBuilder {
-            input: input,
-            output: "".to_string(),
+
+impl Default for Argument {
+    /// the defaults of the command line, training from no input
+    fn default() -> Argument {
+        Argument {
+            input: String::new(),
+            format: InputFormat::Text,
+            output: String::new(),
             lr: 0.05,
             dim: 100,
             win: 5,
@@ -149,177 +267,437 @@ impl ArgumentBuilder {
             nthreads: 12,
             min_count: 5,
             threshold: 1e-4,
-            lr_update: 100,
-            command: command,
+            lr_update: 5000,
+            command: Command::Train,
             verbose: false,
             max_rows: None,
+            corpus_cache: None,
+            tokenizer: Tokenizer::default(),
+            max_vocab_size: None,
+            max_final_vocab: None,
+            read_vocab: None,
+            oov: Oov::Error,
+            neg_pow: 0.75,
+            noise_weights: None,
+            seed: None,
+            deterministic: false,
+            lr_schedule: LrSchedule::Linear,
+            min_lr: 0.0001,
+            warmup: 0.,
+            lr_decay: 0.5,
+            metrics: None,
+            valid: None,
+            valid_similarity: None,
+            valid_analogy: None,
+            valid_metric: None,
+            patience: None,
+            address: DEFAULT_ADDRESS.to_string(),
+            hnsw: HnswParams::default(),
         }
     }
-    #[allow(dead_code)]
-    fn output(&mut self, output: String) -> &mut Self {
-        self.output = output;
+}
+
+/// Builds the `Argument` of a training run, starting from the defaults of
+/// the command line. `build` checks the values.
+///
+/// ```no_run
+/// use word2vec::{ArgumentBuilder, Command, Trainer};
+///
+/// let args = ArgumentBuilder::new("corpus.txt".to_string(), Command::Train)
+///     .dim(300)
+///     .min_count(10)
+///     .build()?;
+/// let model = Trainer::new(args)?.train()?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct ArgumentBuilder {
+    args: Argument,
+}
+impl ArgumentBuilder {
+    /// the defaults for reading `input`, - for stdin
+    pub fn new(input: String, command: Command) -> ArgumentBuilder {
+        ArgumentBuilder {
+            args: Argument {
+                format: InputFormat::from_args(&input, None, None, CsvFormat::default()),
+                input,
+                command,
+                ..Argument::default()
+            },
+        }
+    }
+    /// input format, guessed from the extension of the input by default
+    pub fn format(&mut self, format: InputFormat) -> &mut Self {
+        self.args.format = format;
+        self
+    }
+    /// file the command line saves to, not used by `Trainer`
+    pub fn output(&mut self, output: String) -> &mut Self {
+        self.args.output = output;
+        self
+    }
+    /// initial learning rate(0.05)
+    pub fn lr(&mut self, lr: f32) -> &mut Self {
+        self.args.lr = lr;
+        self
+    }
+    /// size of the word vectors(100)
+    pub fn dim(&mut self, dim: usize) -> &mut Self {
+        self.args.dim = dim;
+        self
+    }
+    /// largest distance of a context word(5)
+    pub fn win(&mut self, win: usize) -> &mut Self {
+        self.args.win = win;
+        self
+    }
+    /// passes over the corpus(5)
+    pub fn epoch(&mut self, epoch: u32) -> &mut Self {
+        self.args.epoch = epoch;
+        self
+    }
+    /// negative samples per context word(5)
+    pub fn neg(&mut self, neg: usize) -> &mut Self {
+        self.args.neg = neg;
+        self
+    }
+    /// training and counting threads(12)
+    pub fn threads(&mut self, threads: u32) -> &mut Self {
+        self.args.nthreads = threads;
+        self
+    }
+    /// words seen less often are left out(5)
+    pub fn min_count(&mut self, min_count: u32) -> &mut Self {
+        self.args.min_count = min_count;
+        self
+    }
+    /// frequent words are subsampled above this frequency(1e-4)
+    pub fn threshold(&mut self, threshold: f32) -> &mut Self {
+        self.args.threshold = threshold;
+        self
+    }
+    /// tokens of a thread between learning rate updates(5000)
+    pub fn lr_update(&mut self, lr_update: u32) -> &mut Self {
+        self.args.lr_update = lr_update;
+        self
+    }
+    /// report more through the observer of the command line
+    pub fn verbose(&mut self, verbose: bool) -> &mut Self {
+        self.args.verbose = verbose;
+        self
+    }
+    /// read at most this many records
+    pub fn max_rows(&mut self, max_rows: Option<usize>) -> &mut Self {
+        self.args.max_rows = max_rows;
+        self
+    }
+    /// file to encode the corpus into once and train all epochs from
+    pub fn corpus_cache(&mut self, corpus_cache: Option<String>) -> &mut Self {
+        self.args.corpus_cache = corpus_cache;
+        self
+    }
+    /// how text is split into words
+    pub fn tokenizer(&mut self, tokenizer: Tokenizer) -> &mut Self {
+        self.args.tokenizer = tokenizer;
         self
     }
-    #[allow(dead_code)]
-    fn lr(&mut self, lr: f32) -> &mut Self {
-        self.lr = lr;
+    /// prune the rarest words while counting above this many
+    pub fn max_vocab_size(&mut self, max_vocab_size: Option<usize>) -> &mut Self {
+        self.args.max_vocab_size = max_vocab_size;
         self
     }
-    #[allow(dead_code)]
-    fn dim(&mut self, dim: usize) -> &mut Self {
-        self.dim = dim;
+    /// keep at most this many words
+    pub fn max_final_vocab(&mut self, max_final_vocab: Option<usize>) -> &mut Self {
+        self.args.max_final_vocab = max_final_vocab;
         self
     }
-    #[allow(dead_code)]
-    fn win(&mut self, win: usize) -> &mut Self {
-        self.win = win;
+    /// vocabulary file to use instead of counting
+    pub fn read_vocab(&mut self, read_vocab: Option<String>) -> &mut Self {
+        self.args.read_vocab = read_vocab;
         self
     }
-    #[allow(dead_code)]
-    fn epoch(&mut self, epoch: u32) -> &mut Self {
-        self.epoch = epoch;
+    /// how queries treat words out of the vocabulary(error)
+    pub fn oov(&mut self, oov: Oov) -> &mut Self {
+        self.args.oov = oov;
         self
     }
-    #[allow(dead_code)]
-    fn neg(&mut self, neg: usize) -> &mut Self {
-        self.neg = neg;
+    /// exponent of the counts in the negative sampling distribution(0.75)
+    pub fn neg_pow(&mut self, neg_pow: f32) -> &mut Self {
+        self.args.neg_pow = neg_pow;
         self
     }
-    #[allow(dead_code)]
-    fn threads(&mut self, threads: u32) -> &mut Self {
-        self.nthreads = threads;
+    /// file of word weights to draw negative samples from
+    pub fn noise_weights(&mut self, noise_weights: Option<String>) -> &mut Self {
+        self.args.noise_weights = noise_weights;
         self
     }
-    #[allow(dead_code)]
-    fn min_count(&mut self, min_count: u32) -> &mut Self {
-        self.min_count = min_count;
+    /// seed of every random generator, random by default
+    pub fn seed(&mut self, seed: Option<u64>) -> &mut Self {
+        self.args.seed = seed;
         self
     }
-    #[allow(dead_code)]
-    fn threshold(&mut self, threshold: f32) -> &mut Self {
-        self.threshold = threshold;
+    /// train on one thread for repeatable vectors
+    pub fn deterministic(&mut self, deterministic: bool) -> &mut Self {
+        self.args.deterministic = deterministic;
         self
     }
-    #[allow(dead_code)]
-    fn lr_update(&mut self, lr_update: u32) -> &mut Self {
-        self.lr_update = lr_update;
+    /// how the learning rate decays(linear)
+    pub fn lr_schedule(&mut self, lr_schedule: LrSchedule) -> &mut Self {
+        self.args.lr_schedule = lr_schedule;
         self
     }
-    #[allow(dead_code)]
-    fn verbose(&mut self, verbose: bool) -> &mut Self {
-        self.verbose = verbose;
+    /// lowest learning rate(0.0001)
+    pub fn min_lr(&mut self, min_lr: f32) -> &mut Self {
+        self.args.min_lr = min_lr;
         self
     }
-    #[allow(dead_code)]
-    fn max_rows(&mut self, max_rows: Option<usize>) -> &mut Self {
-        self.max_rows = max_rows;
+    /// part of training over which the learning rate rises(0)
+    pub fn warmup(&mut self, warmup: f32) -> &mut Self {
+        self.args.warmup = warmup;
         self
     }
-    #[allow(dead_code)]
+    /// learning rate factor per epoch of LrSchedule::Step(0.5)
+    pub fn lr_decay(&mut self, lr_decay: f32) -> &mut Self {
+        self.args.lr_decay = lr_decay;
+        self
+    }
+    /// csv or json lines file to log training metrics to
+    pub fn metrics(&mut self, metrics: Option<String>) -> &mut Self {
+        self.args.metrics = metrics;
+        self
+    }
+    /// held-out corpus evaluated after every epoch
+    pub fn valid(&mut self, valid: Option<String>) -> &mut Self {
+        self.args.valid = valid;
+        self
+    }
+    /// word similarity benchmark evaluated after every epoch
+    pub fn valid_similarity(&mut self, valid_similarity: Option<String>) -> &mut Self {
+        self.args.valid_similarity = valid_similarity;
+        self
+    }
+    /// analogy benchmark evaluated after every epoch
+    pub fn valid_analogy(&mut self, valid_analogy: Option<String>) -> &mut Self {
+        self.args.valid_analogy = valid_analogy;
+        self
+    }
+    /// what picks the best epoch, the first held-out data given by default
+    pub fn valid_metric(&mut self, valid_metric: Option<ValidMetric>) -> &mut Self {
+        self.args.valid_metric = valid_metric;
+        self
+    }
+    /// stop after this many epochs without improvement
+    pub fn patience(&mut self, patience: Option<u32>) -> &mut Self {
+        self.args.patience = patience;
+        self
+    }
+    /// address the serve command listens on(127.0.0.1:8080)
+    pub fn address(&mut self, address: String) -> &mut Self {
+        self.args.address = address;
+        self
+    }
+    /// parameters of the index command
+    pub fn hnsw(&mut self, hnsw: HnswParams) -> &mut Self {
+        self.args.hnsw = hnsw;
+        self
+    }
+    /// the argument, or the first option with an invalid value
+    pub fn build(&self) -> Result<Argument, ArgumentError> {
+        let argument = self.finalize();
+        argument.validate()?;
+        Ok(argument)
+    }
     fn finalize(&self) -> Argument {
-        Argument {
-            input: self.input.to_owned(),
-            output: self.output.to_owned(),
-            win: self.win,
-            epoch: self.epoch,
-            lr: self.lr,
-            dim: self.dim,
-            neg: self.neg,
-            nthreads: self.nthreads,
-            min_count: self.min_count,
-            threshold: self.threshold,
-            lr_update: self.lr_update,
-            command: self.command,
-            verbose: self.verbose,
-            max_rows: self.max_rows,
-        }
+        self.args.clone()
     }
 }
 
-pub fn parse_arguments<'a>(args: &'a Vec<String>) -> Result<Argument, ArgumentError> {
-    let app = clap_app!(word2vec =>
-        (version: "1.0")
-        (author: "Frank Lee <golifang1234@gmail.com>")
-        (about: "word2vec implemention for rust")
-        (@subcommand test =>
-        (about: "test word similarity")
-        (@arg input:+required "input parameter file path( use train subcommand to train a model)")
-        (@arg verbose: --verbose "print internal log")
+// options for reading and counting the corpus, shared by train and vocab
+fn corpus_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    let option = |name: &'a str, help: &'a str| {
+        Arg::with_name(name).long(name).takes_value(true).help(help)
+    };
+    let flag = |name: &'a str, help: &'a str| Arg::with_name(name).long(name).help(help);
+    vec![
+        option(
+            "config",
+            "toml or json file of option values, like dim = 300, under the options given here",
+        ),
+        option("min_count", "number of word occurences(5)"),
+        option("thread", "number of threads(12)"),
+        flag("verbose", "print internal log"),
+        option("max_rows", "maximal rows from the training corpus"),
+        option("format", "input format(guessed from the file extension)")
+            .possible_values(&["text", "jsonl", "csv", "tsv", "parquet"]),
+        option(
+            "field",
+            "jsonl field holding the text, as a json pointer or dotted path(text)",
+        ),
+        option(
+            "column",
+            "csv column holding the text, by name or index from 0(text)",
+        ),
+        option(
+            "tag_column",
+            "csv column with a document tag trained along the words",
+        ),
+        option(
+            "weight_column",
+            "csv column with a learning rate weight for the record",
+        ),
+        flag("no_header", "csv input has no header row"),
+        option(
+            "max_vocab_size",
+            "words kept while counting, the least frequent are pruned beyond it",
+        ),
+        option("max_final_vocab", "keep only this many most frequent words"),
+        flag("lowercase", "lowercase the text"),
+        flag("nfkc", "apply unicode NFKC normalization to the text"),
+        option("punctuation", "punctuation around words(keep)")
+            .possible_values(&["keep", "strip", "split"]),
+        flag(
+            "unicode_words",
+            "split words at unicode word boundaries instead of whitespace",
+        ),
+        flag("normalize_numbers", "replace numbers with <num>"),
+        option(
+            "oov",
+            "out of vocabulary words in queries, unk trains a vector for the words left out(error)",
         )
-       (@subcommand train =>
+        .possible_values(&["error", "skip", "zero", "unk"]),
+    ]
+}
+
+fn app<'a, 'b>() -> clap::App<'a, 'b> {
+    let train = clap_app!(train =>
             (about: "train model")
             (version: "0.1")
          //argument
-        (@arg input: +required "input corpus file path")
-        (@arg output: +required "file name to save params")
+        (@arg input: "input corpus file path, - to read it from stdin")
+        (@arg output: "file name to save params")
         //options
         (@arg win: --win +takes_value "window size(5)")
         (@arg neg: --neg +takes_value "negative sampling size(5)")
         (@arg lr: --lr +takes_value "learning rate(0.05)")
-        (@arg lr_update: --lr_update +takes_value "learning rate update rate(100)")
+        (@arg lr_update: --lr_update +takes_value "learning rate update rate(5000)")
+        (@arg lr_schedule: --lr_schedule +takes_value possible_value[linear cosine step constant] "learning rate schedule(linear)")
+        (@arg min_lr: --min_lr +takes_value "lowest learning rate(0.0001)")
+        (@arg warmup: --warmup +takes_value "part of training over which the learning rate rises to lr(0)")
+        (@arg lr_decay: --lr_decay +takes_value "learning rate factor per epoch for the step schedule(0.5)")
         (@arg dim: --dim +takes_value "size of word vectors(100)")
         (@arg epoch: --epoch +takes_value "number of epochs(5)")
-        (@arg min_count: --min_count +takes_value "number of word occurences(5)")
-        (@arg nthreads: --thread +takes_value "number of threads(12)")
         (@arg threshold: --threshold +takes_value "sampling threshold(1e-4)")
-        (@arg verbose: --verbose "print internal log")
-        (@arg max_rows: --max_rows +takes_value "maximal rows from the training corpus")
-       )
+        (@arg neg_pow: --neg_pow +takes_value "exponent of the word counts in the negative sampling distribution, 0 for uniform(0.75)")
+        (@arg seed: --seed +takes_value "seed of all random numbers(random)")
+        (@arg deterministic: --deterministic "train on one thread, giving the same vectors for the same seed")
+        (@arg noise_weights: --noise_weights +takes_value "file of word<TAB>weight lines to draw negative samples from instead of the counts")
+        (@arg corpus_cache: --corpus_cache +takes_value "file to encode the corpus into once and train all epochs from")
+        (@arg metrics: --metrics +takes_value "file to log loss, learning rate and words/sec per epoch and interval to, csv for a .csv name, json lines otherwise")
+        (@arg valid: --valid +takes_value "held-out corpus, in the input format, whose loss is evaluated after every epoch")
+        (@arg valid_similarity: --valid_similarity +takes_value "word1 word2 score file to evaluate after every epoch")
+        (@arg valid_analogy: --valid_analogy +takes_value "a b c d analogy file to evaluate after every epoch")
+        (@arg valid_metric: --valid_metric +takes_value possible_value[loss similarity analogy] "what picks the best epoch, whose vectors are kept(the first given)")
+        (@arg patience: --patience +takes_value "stop after this many epochs without improvement(never)")
+        (@arg read_vocab: --read_vocab +takes_value "vocabulary file written by the vocab subcommand, skips counting")
+    )
+    .args(&corpus_args());
+    let vocab = clap_app!(vocab =>
+        (about: "count the words of a corpus and save the vocabulary")
+        (@arg input: "input corpus file path, - to read it from stdin")
+        (@arg output: "file name to save the vocabulary")
+    )
+    .args(&corpus_args());
+    let serve = clap_app!(serve =>
+        (about: "answer queries to a saved model over http")
+        (@arg input: +required "model file path, as saved by train")
+        (@arg address: --address +takes_value "host:port to listen on(127.0.0.1:8080)")
+        (@arg thread: --thread +takes_value "threads answering requests(12)")
+        (@arg oov: --oov +takes_value possible_value[error skip zero unk] "out of vocabulary words in queries(error)")
     );
-    let matches = app.get_matches_from(args);
+    let index = clap_app!(index =>
+        (about: "build the approximate nearest neighbour index of a saved model, saved next to it")
+        (@arg input: +required "model file path, as saved by train")
+        (@arg m: --m +takes_value "links per word and layer, twice that in the bottom layer(16)")
+        (@arg ef_construction: --ef_construction +takes_value "candidates considered when linking a word(200)")
+        (@arg ef_search: --ef_search +takes_value "candidates considered by queries(64)")
+        (@arg recall_queries: --recall_queries +takes_value "words whose neighbours are compared with exact search(1000)")
+        (@arg thread: --thread +takes_value "threads building the index(12)")
+        (@arg seed: --seed +takes_value "seed of the levels of the words(0)")
+    );
+    clap_app!(word2vec =>
+        (version: "1.0")
+        (author: "Frank Lee <golifang1234@gmail.com>")
+        (about: "word2vec implemention for rust")
+        (@subcommand test =>
+        (about: "test word similarity")
+        (@arg input:+required "input parameter file path( use train subcommand to train a model)")
+        (@arg verbose: --verbose "print internal log")
+        )
+    )
+    .subcommand(train)
+    .subcommand(vocab)
+    .subcommand(serve)
+    .subcommand(index)
+}
+
+pub fn parse_arguments(args: &[String]) -> Result<Argument, ArgumentError> {
+    // clap errors, --help included, are returned instead of exiting, the
+    // command line exits with them itself
+    let mut matches = app().get_matches_from_safe(args)?;
+    // the options of a config file are parsed as if they followed the others
+    // vocab takes the options it shares with train from a config of both
+    let corpus_names: Vec<&str> = corpus_args().iter().map(|arg| arg.b.name).collect();
+    let config = ["train", "vocab"].iter().find_map(|&command| {
+        let m = matches.subcommand_matches(command)?;
+        let path = m.value_of("config")?;
+        let known = |name: &str| {
+            command == "train" || name == "input" || name == "output" || corpus_names.contains(&name)
+        };
+        Some(config_args(path, &|name| m.occurrences_of(name) > 0, &known))
+    });
+    if let Some(config) = config {
+        let mut args = args.to_vec();
+        args.extend(config?);
+        matches = app().get_matches_from_safe(&args)?;
+    }
 
     if let Some(train_info) = matches.subcommand_matches("train") {
-        let input = train_info
+        let argument = argument_from_matches(train_info, Command::Train)?;
+        argument.validate()?;
+        Ok(argument)
+    } else if let Some(vocab_info) = matches.subcommand_matches("vocab") {
+        let argument = argument_from_matches(vocab_info, Command::Vocab)?;
+        argument.validate()?;
+        Ok(argument)
+    } else if let Some(serve_info) = matches.subcommand_matches("serve") {
+        let input = serve_info
             .value_of("input")
-            .ok_or(clap::Error::argument_not_found_auto("input"));
-        let output = train_info
-            .value_of("output")
-            .ok_or(clap::Error::argument_not_found_auto("output"));
-        let win = train_info.value_of("win").unwrap_or("5").parse::<usize>()?;
-        let neg = train_info.value_of("neg").unwrap_or("5").parse::<usize>()?;
-        let lr = train_info.value_of("lr").unwrap_or("0.05").parse::<f32>()?;
-        let lr_update = train_info
-            .value_of("lr_update")
-            .unwrap_or("5000")
-            .parse::<u32>()?;
-        let vector_size = train_info
-            .value_of("dim")
-            .unwrap_or("100")
-            .parse::<usize>()?;
-        let epoch = train_info.value_of("epoch").unwrap_or("5").parse::<u32>()?;
-        let min_count = train_info
-            .value_of("min_count")
-            .unwrap_or("5")
-            .parse::<u32>()?;
-        let nthreads = train_info
-            .value_of("nthreads")
-            .unwrap_or("12")
-            .parse::<u32>()?;
-        let threshold = train_info
-            .value_of("threshold")
-            .unwrap_or("1e-4")
-            .parse::<f32>()?;
-        println!("{}", train_info.value_of("max_rows").unwrap_or("lolz"));
-        let max_rows = match train_info.value_of("max_rows") {
-            Some(s) => Some(str::parse::<usize>(s)?),
-            None => None,
+            .ok_or(clap::Error::argument_not_found_auto("input"))?;
+        let mut builder = ArgumentBuilder::new(input.to_string(), Command::Serve);
+        builder.threads(serve_info.value_of("thread").unwrap_or("12").parse::<u32>()?);
+        if let Some(address) = serve_info.value_of("address") {
+            builder.address(address.to_string());
+        }
+        if let Some(oov) = serve_info.value_of("oov").and_then(Oov::parse) {
+            builder.oov(oov);
+        }
+        builder.build()
+    } else if let Some(index_info) = matches.subcommand_matches("index") {
+        let input = index_info
+            .value_of("input")
+            .ok_or(clap::Error::argument_not_found_auto("input"))?;
+        let mut hnsw = HnswParams::default();
+        let value = |name: &str, default: usize| -> Result<usize, ArgumentError> {
+            Ok(index_info.value_of(name).map(str::parse).transpose()?.unwrap_or(default))
         };
-        Ok(Argument {
-            input: input?.to_string(),
-            output: output?.to_string(),
-            lr: lr,
-            dim: vector_size,
-            win: win,
-            epoch: epoch,
-            neg: neg,
-            nthreads: nthreads,
-            min_count: min_count,
-            threshold: threshold,
-            lr_update: lr_update,
-            command: Command::Train,
-            verbose: train_info.is_present("verbose"),
-            max_rows: max_rows,
-        })
+        hnsw.m = value("m", hnsw.m)?;
+        hnsw.ef_construction = value("ef_construction", hnsw.ef_construction)?;
+        hnsw.ef_search = value("ef_search", hnsw.ef_search)?;
+        hnsw.recall_queries = value("recall_queries", hnsw.recall_queries)?;
+        hnsw.seed = index_info.value_of("seed").map(str::parse).transpose()?.unwrap_or(0);
+        ArgumentBuilder::new(input.to_string(), Command::Index)
+            .threads(index_info.value_of("thread").unwrap_or("12").parse::<u32>()?)
+            .hnsw(hnsw)
+            .build()
     } else if let Some(ref test_info) = matches.subcommand_matches("test") {
         let input = test_info
             .value_of("input")
@@ -333,3 +711,209 @@ pub fn parse_arguments<'a>(args: &'a Vec<String>) -> Result<Argument, ArgumentEr
         ))
     }
 }
+
+// options missing from the subcommand take their default value
+fn argument_from_matches(
+    train_info: &ArgMatches,
+    command: Command,
+) -> Result<Argument, ArgumentError> {
+    let input = train_info
+        .value_of("input")
+        .ok_or(clap::Error::argument_not_found_auto("input"));
+    let output = train_info
+        .value_of("output")
+        .ok_or(clap::Error::argument_not_found_auto("output"));
+    let win = train_info.value_of("win").unwrap_or("5").parse::<usize>()?;
+    let neg = train_info.value_of("neg").unwrap_or("5").parse::<usize>()?;
+    let lr = train_info.value_of("lr").unwrap_or("0.05").parse::<f32>()?;
+    let lr_update = train_info
+        .value_of("lr_update")
+        .unwrap_or("5000")
+        .parse::<u32>()?;
+    let vector_size = train_info
+        .value_of("dim")
+        .unwrap_or("100")
+        .parse::<usize>()?;
+    let epoch = train_info.value_of("epoch").unwrap_or("5").parse::<u32>()?;
+    let min_count = train_info
+        .value_of("min_count")
+        .unwrap_or("5")
+        .parse::<u32>()?;
+    let nthreads = train_info
+        .value_of("thread")
+        .unwrap_or("12")
+        .parse::<u32>()?;
+    let threshold = train_info
+        .value_of("threshold")
+        .unwrap_or("1e-4")
+        .parse::<f32>()?;
+    let min_lr = train_info
+        .value_of("min_lr")
+        .unwrap_or("0.0001")
+        .parse::<f32>()?;
+    let warmup = train_info
+        .value_of("warmup")
+        .unwrap_or("0")
+        .parse::<f32>()?;
+    let lr_decay = train_info
+        .value_of("lr_decay")
+        .unwrap_or("0.5")
+        .parse::<f32>()?;
+    let patience = match train_info.value_of("patience") {
+        Some(s) => Some(s.parse::<u32>()?),
+        None => None,
+    };
+    let seed = match train_info.value_of("seed") {
+        Some(s) => Some(s.parse::<u64>()?),
+        None => None,
+    };
+    let neg_pow = train_info
+        .value_of("neg_pow")
+        .unwrap_or("0.75")
+        .parse::<f32>()?;
+    let max_rows = match train_info.value_of("max_rows") {
+        Some(s) => Some(str::parse::<usize>(s)?),
+        None => None,
+    };
+    let max_vocab_size = match train_info.value_of("max_vocab_size") {
+        Some(s) => Some(str::parse::<usize>(s)?),
+        None => None,
+    };
+    let max_final_vocab = match train_info.value_of("max_final_vocab") {
+        Some(s) => Some(str::parse::<usize>(s)?),
+        None => None,
+    };
+    let input = input?.to_string();
+    Ok(Argument {
+        format: InputFormat::from_args(
+            &input,
+            train_info.value_of("format"),
+            train_info.value_of("field"),
+            CsvFormat {
+                delimiter: b',',
+                header: !train_info.is_present("no_header"),
+                text: Column::parse(train_info.value_of("column").unwrap_or("text")),
+                tag: train_info.value_of("tag_column").map(Column::parse),
+                weight: train_info.value_of("weight_column").map(Column::parse),
+            },
+        ),
+        input,
+        output: output?.to_string(),
+        lr,
+        dim: vector_size,
+        win,
+        epoch,
+        neg,
+        nthreads,
+        min_count,
+        threshold,
+        lr_update,
+        command,
+        verbose: train_info.is_present("verbose"),
+        max_rows,
+        corpus_cache: train_info.value_of("corpus_cache").map(str::to_string),
+        read_vocab: train_info.value_of("read_vocab").map(str::to_string),
+        tokenizer: Tokenizer {
+            lowercase: train_info.is_present("lowercase"),
+            nfkc: train_info.is_present("nfkc"),
+            punctuation: train_info
+                .value_of("punctuation")
+                .and_then(Punctuation::parse)
+                .unwrap_or(Punctuation::Keep),
+            unicode_words: train_info.is_present("unicode_words"),
+            numbers: train_info.is_present("normalize_numbers"),
+        },
+        max_vocab_size,
+        max_final_vocab,
+        oov: train_info
+            .value_of("oov")
+            .and_then(Oov::parse)
+            .unwrap_or(Oov::Error),
+        neg_pow,
+        noise_weights: train_info.value_of("noise_weights").map(str::to_string),
+        seed,
+        deterministic: train_info.is_present("deterministic"),
+        lr_schedule: train_info
+            .value_of("lr_schedule")
+            .and_then(LrSchedule::parse)
+            .unwrap_or(LrSchedule::Linear),
+        min_lr,
+        warmup,
+        lr_decay,
+        metrics: train_info.value_of("metrics").map(str::to_string),
+        valid: train_info.value_of("valid").map(str::to_string),
+        valid_similarity: train_info.value_of("valid_similarity").map(str::to_string),
+        valid_analogy: train_info.value_of("valid_analogy").map(str::to_string),
+        valid_metric: train_info.value_of("valid_metric").and_then(ValidMetric::parse),
+        patience,
+        address: DEFAULT_ADDRESS.to_string(),
+        hnsw: HnswParams::default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builder() -> ArgumentBuilder {
+        ArgumentBuilder::new("corpus.txt".to_string(), Command::Train)
+    }
+
+    // the option named by the error of `build`, None when it builds
+    fn invalid(builder: &ArgumentBuilder) -> Option<&'static str> {
+        match builder.build() {
+            Ok(_) => None,
+            Err(ArgumentError::Invalid(option, _)) => Some(option),
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    #[test]
+    fn builder_defaults_are_the_command_line_defaults() {
+        let line: Vec<String> = ["word2vec", "train", "corpus.txt", "out"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let parsed = parse_arguments(&line).unwrap();
+        let built = builder().output("out".to_string()).build().unwrap();
+        assert_eq!(parsed.config(), built.config());
+    }
+
+    #[test]
+    fn validate_names_the_option() {
+        assert_eq!(invalid(&builder()), None);
+        assert_eq!(invalid(builder().dim(0)), Some("dim"));
+        assert_eq!(invalid(builder().win(0)), Some("win"));
+        assert_eq!(invalid(builder().threads(0)), Some("thread"));
+        assert_eq!(invalid(builder().min_count(0)), Some("min_count"));
+        assert_eq!(invalid(builder().lr(f32::NAN)), Some("lr"));
+        assert_eq!(invalid(builder().lr(0.01).min_lr(0.1)), Some("min_lr"));
+        assert_eq!(invalid(builder().warmup(1.)), Some("warmup"));
+        assert_eq!(invalid(builder().threshold(-1.)), Some("threshold"));
+        assert_eq!(
+            invalid(builder().max_vocab_size(Some(0))),
+            Some("max_vocab_size")
+        );
+        assert_eq!(invalid(builder().patience(Some(2))), Some("patience"));
+        let valid = Some("valid.txt".to_string());
+        assert_eq!(invalid(builder().patience(Some(2)).valid(valid)), None);
+        let mut metric = builder();
+        metric
+            .valid_metric(Some(ValidMetric::Analogy))
+            .valid(Some("v".to_string()));
+        assert_eq!(invalid(&metric), Some("valid_metric"));
+    }
+
+    #[test]
+    fn validate_checks_index_parameters_for_index_only() {
+        let hnsw = HnswParams {
+            m: 1,
+            ..HnswParams::default()
+        };
+        assert_eq!(invalid(builder().hnsw(hnsw)), None);
+        let index = ArgumentBuilder::new("model".to_string(), Command::Index)
+            .hnsw(hnsw)
+            .build();
+        assert!(matches!(index, Err(ArgumentError::Invalid("m", _))));
+    }
+}
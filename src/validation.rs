@@ -0,0 +1,532 @@
+use crate::sampler::{seeded_rng, AliasTable};
+use crate::corpus::record_error;
+use crate::{dot_product, Argument, Dict, Matrix, W2vError};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::sync::{Barrier, Mutex};
+
+// analogy answers are searched among this many of the most frequent words,
+// as in the original questions-words evaluation
+const ANALOGY_VOCAB: usize = 30000;
+// random numbers of the held-out loss, the same for every epoch
+const VALID_STREAM: u64 = u64::MAX;
+
+// what epochs are compared by: the lowest held-out loss, or the highest
+// similarity correlation or analogy accuracy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidMetric {
+    Loss,
+    Similarity,
+    Analogy,
+}
+
+impl ValidMetric {
+    pub fn parse(s: &str) -> Option<ValidMetric> {
+        match s {
+            "loss" => Some(ValidMetric::Loss),
+            "similarity" => Some(ValidMetric::Similarity),
+            "analogy" => Some(ValidMetric::Analogy),
+            _ => None,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            ValidMetric::Loss => "loss",
+            ValidMetric::Similarity => "similarity",
+            ValidMetric::Analogy => "analogy",
+        }
+    }
+}
+
+// how the vectors of an epoch do on held-out data, None for data not given
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Scores {
+    // mean negative sampling loss of the held-out corpus
+    pub loss: Option<f64>,
+    // spearman correlation of the cosine similarities with the benchmark
+    pub similarity: Option<f64>,
+    // part of the analogies answered right
+    pub analogy: Option<f64>,
+}
+
+impl Scores {
+    // the score of `metric`, higher is better
+    fn get(&self, metric: ValidMetric) -> f64 {
+        let score = match metric {
+            ValidMetric::Loss => self.loss.map(|loss| -loss),
+            ValidMetric::Similarity => self.similarity,
+            ValidMetric::Analogy => self.analogy,
+        };
+        // no score, of too little data, never improves
+        score.unwrap_or(f64::NEG_INFINITY)
+    }
+}
+
+struct Best {
+    epoch: u32,
+    score: f64,
+    input: Matrix,
+    output: Matrix,
+}
+
+struct State {
+    scores: Scores,
+    best: Option<Best>,
+    // a thread failed reading its part of the epoch
+    failed: bool,
+    stop: bool,
+}
+
+// evaluates the vectors at the end of every epoch, keeps those of the best
+// epoch and stops training after --patience epochs without improvement
+pub struct Validation {
+    corpus: Option<Vec<Vec<usize>>>,
+    similarity: Option<Vec<(usize, usize, f64)>>,
+    analogy: Option<Vec<[usize; 4]>>,
+    metric: ValidMetric,
+    patience: Option<u32>,
+    win: usize,
+    neg: usize,
+    seed: u64,
+    // the threads wait at it before and after the first one evaluates
+    barrier: Barrier,
+    state: Mutex<State>,
+}
+
+impl Validation {
+    // None when no held-out data is given. the options are validated
+    pub fn new(
+        args: &Argument,
+        dict: &Dict,
+        threads: usize,
+    ) -> Result<Option<Validation>, W2vError> {
+        let corpus = args
+            .valid
+            .as_deref()
+            .map(|f| read_corpus(f, args, dict))
+            .transpose()?;
+        let similarity = args
+            .valid_similarity
+            .as_deref()
+            .map(|f| read_similarity(f, dict))
+            .transpose()?;
+        let analogy = args
+            .valid_analogy
+            .as_deref()
+            .map(|f| read_analogy(f, dict))
+            .transpose()?;
+        let given = |metric| match metric {
+            ValidMetric::Loss => corpus.is_some(),
+            ValidMetric::Similarity => similarity.is_some(),
+            ValidMetric::Analogy => analogy.is_some(),
+        };
+        let metric = match args.valid_metric {
+            Some(metric) => metric,
+            None => match [
+                ValidMetric::Loss,
+                ValidMetric::Similarity,
+                ValidMetric::Analogy,
+            ]
+            .into_iter()
+            .find(|&metric| given(metric))
+            {
+                Some(metric) => metric,
+                None => return Ok(None),
+            },
+        };
+        Ok(Some(Validation {
+            corpus,
+            similarity,
+            analogy,
+            metric,
+            patience: args.patience,
+            win: args.win,
+            neg: args.neg,
+            seed: args.seed.unwrap_or(0),
+            barrier: Barrier::new(threads),
+            state: Mutex::new(State {
+                scores: Scores::default(),
+                best: None,
+                failed: false,
+                stop: false,
+            }),
+        }))
+    }
+
+    // called by every training thread at the end of each epoch, with whether
+    // reading its part failed. once all have ended it the first thread
+    // evaluates the epoch, then all of them learn whether to stop
+    pub fn end_epoch(
+        &self,
+        tid: u32,
+        epoch: u32,
+        input: &Matrix,
+        output: &Matrix,
+        neg_table: &AliasTable,
+        failed: bool,
+    ) -> bool {
+        if failed {
+            self.state.lock().unwrap().failed = true;
+        }
+        self.barrier.wait();
+        if tid == 0 {
+            let mut state = self.state.lock().unwrap();
+            if !state.failed {
+                state.scores = self.scores(input, output, neg_table);
+                let score = state.scores.get(self.metric);
+                match state.best {
+                    Some(ref mut best) if score <= best.score => {}
+                    Some(ref mut best) => {
+                        best.input.clone_from(input);
+                        best.output.clone_from(output);
+                        best.epoch = epoch;
+                        best.score = score;
+                    }
+                    None => {
+                        state.best = Some(Best {
+                            epoch,
+                            score,
+                            input: input.clone(),
+                            output: output.clone(),
+                        })
+                    }
+                }
+            }
+            let best_epoch = state.best.as_ref().map_or(epoch, |best| best.epoch);
+            state.stop = state.failed || self.patience.is_some_and(|p| epoch - best_epoch >= p);
+        }
+        self.barrier.wait();
+        self.state.lock().unwrap().stop
+    }
+
+    // the scores of the last epoch evaluated and the best epoch so far
+    pub fn last(&self) -> (Scores, u32) {
+        let state = self.state.lock().unwrap();
+        (
+            state.scores,
+            state.best.as_ref().map_or(0, |best| best.epoch),
+        )
+    }
+
+    // the input and output vectors of the best epoch
+    pub fn into_best(self) -> Option<(Matrix, Matrix)> {
+        let best = self.state.into_inner().unwrap().best?;
+        Some((best.input, best.output))
+    }
+
+    fn scores(&self, input: &Matrix, output: &Matrix, neg_table: &AliasTable) -> Scores {
+        Scores {
+            loss: self
+                .corpus
+                .as_ref()
+                .and_then(|corpus| self.loss(corpus, input, output, neg_table)),
+            similarity: self
+                .similarity
+                .as_ref()
+                .and_then(|pairs| similarity(pairs, input)),
+            analogy: self
+                .analogy
+                .as_ref()
+                .and_then(|questions| analogy(questions, input)),
+        }
+    }
+
+    // the training loss over all pairs of the full window, without updates,
+    // None without pairs
+    fn loss(
+        &self,
+        corpus: &[Vec<usize>],
+        input: &Matrix,
+        output: &Matrix,
+        neg_table: &AliasTable,
+    ) -> Option<f64> {
+        let mut rng = seeded_rng(self.seed, VALID_STREAM);
+        let dot = |i: usize, o: usize| unsafe {
+            dot_product(
+                input.get_row_unmod(i),
+                output.get_row_unmod(o),
+                input.row(i).len(),
+            ) as f64
+        };
+        // -log(sigmoid(x)), without overflow
+        let softplus = |x: f64| x.max(0.) + (-x.abs()).exp().ln_1p();
+        let (mut loss, mut pairs) = (0f64, 0usize);
+        for line in corpus {
+            for w in 0..line.len() {
+                let context = w.saturating_sub(self.win)..(w + self.win + 1).min(line.len());
+                for c in context.filter(|&c| c != w) {
+                    let (word, target) = (line[w], line[c]);
+                    loss += softplus(-dot(word, target));
+                    for _ in 0..self.neg {
+                        let negative = loop {
+                            let negative = neg_table.sample(&mut rng);
+                            if negative != target {
+                                break negative;
+                            }
+                        };
+                        loss += softplus(dot(word, negative));
+                    }
+                    pairs += 1;
+                }
+            }
+        }
+        (pairs > 0).then(|| loss / pairs as f64)
+    }
+}
+
+// the held-out corpus, read like the training input but without subsampling
+fn read_corpus(filename: &str, args: &Argument, dict: &Dict) -> Result<Vec<Vec<usize>>, W2vError> {
+    let format = args.format.resolve_columns(filename)?;
+    let reader = BufReader::with_capacity(10000, File::open(filename)?);
+    let mut corpus = Vec::new();
+    let mut ids = Vec::new();
+    for record in format.records(reader, true)? {
+        if let Some(record) = record.map_err(|err| record_error(err, filename, 0))? {
+            dict.encode_line(&record.text, &mut ids);
+            corpus.push(ids.drain(..).map(|id| id as usize).collect());
+        }
+    }
+    Ok(corpus)
+}
+
+// the lines of a benchmark file split at whitespace, without the blank
+// lines, # comments and ": section" headers
+fn benchmark_lines(filename: &str) -> Result<Vec<Vec<String>>, W2vError> {
+    let mut lines = Vec::new();
+    for line in BufReader::new(File::open(filename)?).lines() {
+        let line = line?;
+        let line = line.trim();
+        if !(line.is_empty() || line.starts_with('#') || line.starts_with(':')) {
+            lines.push(line.split_whitespace().map(str::to_string).collect());
+        }
+    }
+    Ok(lines)
+}
+
+// word1 word2 score lines, like WordSim-353 or SimLex-999. pairs with a word
+// out of the vocabulary are left out
+fn read_similarity(filename: &str, dict: &Dict) -> Result<Vec<(usize, usize, f64)>, W2vError> {
+    let mut pairs = Vec::new();
+    for fields in benchmark_lines(filename)? {
+        let score = match fields.get(2).map(|s| s.parse::<f64>()) {
+            Some(Ok(score)) => score,
+            // a header line
+            _ => continue,
+        };
+        if let (Some(a), Some(b)) = (dict.query_idx(&fields[0]), dict.query_idx(&fields[1])) {
+            pairs.push((a, b, score));
+        }
+    }
+    Ok(pairs)
+}
+
+// a b c d lines, a is to b as c is to d, like questions-words.txt. questions
+// with a word out of the ANALOGY_VOCAB most frequent are left out
+fn read_analogy(filename: &str, dict: &Dict) -> Result<Vec<[usize; 4]>, W2vError> {
+    let mut questions = Vec::new();
+    for fields in benchmark_lines(filename)? {
+        let ids: Option<Vec<usize>> = fields
+            .iter()
+            .map(|w| dict.query_idx(w).filter(|&i| i < ANALOGY_VOCAB))
+            .collect();
+        if let Some([a, b, c, d]) = ids.as_deref() {
+            questions.push([*a, *b, *c, *d]);
+        }
+    }
+    Ok(questions)
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f64 {
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    let dot = a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>();
+    if norms > 0. {
+        (dot / norms) as f64
+    } else {
+        0.
+    }
+}
+
+// ranks from 1, ties getting the mean of their ranks
+fn ranks(values: &[f64]) -> Vec<f64> {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|&a, &b| values[a].total_cmp(&values[b]));
+    let mut ranks = vec![0.; values.len()];
+    let mut i = 0;
+    while i < order.len() {
+        let mut j = i;
+        while j + 1 < order.len() && values[order[j + 1]] == values[order[i]] {
+            j += 1;
+        }
+        for &k in &order[i..=j] {
+            ranks[k] = (i + j) as f64 / 2. + 1.;
+        }
+        i = j + 1;
+    }
+    ranks
+}
+
+// spearman correlation of the cosine similarities with the scores, None
+// when it is not defined, for fewer than two pairs or all ranks equal
+fn similarity(pairs: &[(usize, usize, f64)], input: &Matrix) -> Option<f64> {
+    let cosines: Vec<f64> = pairs
+        .iter()
+        .map(|&(a, b, _)| cosine(input.row(a), input.row(b)))
+        .collect();
+    let scores: Vec<f64> = pairs.iter().map(|&(_, _, score)| score).collect();
+    let (x, y) = (ranks(&cosines), ranks(&scores));
+    let n = x.len() as f64;
+    let (mx, my) = (x.iter().sum::<f64>() / n, y.iter().sum::<f64>() / n);
+    let cov: f64 = x.iter().zip(&y).map(|(a, b)| (a - mx) * (b - my)).sum();
+    let var = |v: &[f64], m: f64| v.iter().map(|a| (a - m) * (a - m)).sum::<f64>();
+    Some(cov / (var(&x, mx) * var(&y, my)).sqrt()).filter(|r| r.is_finite())
+}
+
+// part of the questions whose d is the nearest word to b - a + c, leaving
+// out a, b and c. None without questions
+fn analogy(questions: &[[usize; 4]], input: &Matrix) -> Option<f64> {
+    if questions.is_empty() {
+        return None;
+    }
+    let dim = input.row_size();
+    let rows = input.rows().min(ANALOGY_VOCAB);
+    let mut unit = Vec::with_capacity(rows * dim);
+    for i in 0..rows {
+        let row = input.row(i);
+        let norm = row
+            .iter()
+            .map(|x| x * x)
+            .sum::<f32>()
+            .sqrt()
+            .max(f32::MIN_POSITIVE);
+        unit.extend(row.iter().map(|x| x / norm));
+    }
+    let mut query = vec![0f32; dim];
+    let mut right = 0;
+    for &[a, b, c, d] in questions {
+        for (k, q) in query.iter_mut().enumerate() {
+            *q = unit[b * dim + k] - unit[a * dim + k] + unit[c * dim + k];
+        }
+        let nearest = (0..rows)
+            .filter(|&i| i != a && i != b && i != c)
+            .map(|i| {
+                (i, unsafe {
+                    dot_product(unit[i * dim..].as_ptr(), query.as_ptr(), dim)
+                })
+            })
+            .max_by(|x, y| x.1.total_cmp(&y.1));
+        if nearest.map(|(i, _)| i) == Some(d) {
+            right += 1;
+        }
+    }
+    Some(right as f64 / questions.len() as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // three words, word 0 nearer to word 1 than to word 2 when `good`
+    fn vectors(good: bool) -> Matrix {
+        let (near, far) = ([1., 0.1], [0., 1.]);
+        let (one, two) = if good { (near, far) } else { (far, near) };
+        Matrix::from_vec([[1., 0.], one, two].concat(), 2)
+    }
+
+    fn validation(patience: Option<u32>) -> Validation {
+        Validation {
+            corpus: None,
+            similarity: Some(vec![(0, 1, 9.), (0, 2, 1.)]),
+            analogy: None,
+            metric: ValidMetric::Similarity,
+            patience,
+            win: 5,
+            neg: 5,
+            seed: 0,
+            barrier: Barrier::new(1),
+            state: Mutex::new(State {
+                scores: Scores::default(),
+                best: None,
+                failed: false,
+                stop: false,
+            }),
+        }
+    }
+
+    #[test]
+    fn stops_after_patience_and_keeps_the_best_epoch() {
+        let neg_table = AliasTable::new(&[1., 1., 1.]).unwrap();
+        let output = Matrix::new(3, 2);
+        let valid = validation(Some(2));
+        let epochs = [false, true, false, false];
+        let stops: Vec<bool> = epochs
+            .iter()
+            .enumerate()
+            .map(|(epoch, &good)| {
+                valid.end_epoch(0, epoch as u32, &vectors(good), &output, &neg_table, false)
+            })
+            .collect();
+        assert_eq!(stops, [false, false, false, true]);
+        let (scores, best_epoch) = valid.last();
+        assert_eq!(scores.similarity, Some(-1.));
+        assert_eq!(best_epoch, 1);
+        let (input, _) = valid.into_best().unwrap();
+        for i in 0..3 {
+            assert_eq!(input.row(i), vectors(true).row(i));
+        }
+    }
+
+    #[test]
+    fn runs_every_epoch_without_patience() {
+        let neg_table = AliasTable::new(&[1., 1., 1.]).unwrap();
+        let output = Matrix::new(3, 2);
+        let valid = validation(None);
+        for epoch in 0..5 {
+            assert!(!valid.end_epoch(0, epoch, &vectors(epoch == 0), &output, &neg_table, false));
+        }
+        assert_eq!(valid.last().1, 0);
+    }
+
+    #[test]
+    fn a_failed_epoch_stops_and_is_not_scored() {
+        let neg_table = AliasTable::new(&[1., 1., 1.]).unwrap();
+        let output = Matrix::new(3, 2);
+        let valid = validation(None);
+        assert!(valid.end_epoch(0, 0, &vectors(true), &output, &neg_table, true));
+        assert!(valid.into_best().is_none());
+    }
+
+    #[test]
+    fn tied_values_share_their_mean_rank() {
+        assert_eq!(ranks(&[3., 1., 2., 2.]), [4., 1., 2.5, 2.5]);
+        assert_eq!(ranks(&[]), Vec::<f64>::new());
+    }
+
+    #[test]
+    fn spearman_correlation() {
+        // cosines to word 0 of 1, 0.8, 0 and -1
+        let input = Matrix::from_vec(vec![1., 0., 1., 0., 0.8, 0.6, 0., 1., -1., 0.], 2);
+        let pairs = |scores: [f64; 4]| -> Vec<(usize, usize, f64)> {
+            (1..5).zip(scores).map(|(b, s)| (0, b, s)).collect()
+        };
+        assert_eq!(similarity(&pairs([4., 3., 2., 1.]), &input), Some(1.));
+        assert_eq!(similarity(&pairs([1., 2., 3., 4.]), &input), Some(-1.));
+        // ranks 4 3 2 1 against 4 2 3 1
+        let r = similarity(&pairs([9., 1., 5., 0.]), &input).unwrap();
+        assert!((r - 0.8).abs() < 1e-9, "{}", r);
+        // not defined for one pair or equal scores
+        assert_eq!(similarity(&pairs([1., 1., 1., 1.]), &input), None);
+        assert_eq!(similarity(&[(0, 1, 1.)], &input), None);
+    }
+
+    #[test]
+    fn analogy_accuracy() {
+        // x, y, -x, -y and x + y
+        let input = Matrix::from_vec(vec![1., 0., 0., 1., -1., 0., 0., -1., 1., 1.], 2);
+        // x is to x + y as -x is to y, answered right
+        assert_eq!(analogy(&[[0, 4, 2, 1]], &input), Some(1.));
+        // x is to y as -x is to -y, answered x + y instead
+        assert_eq!(analogy(&[[0, 4, 2, 1], [0, 1, 2, 3]], &input), Some(0.5));
+        assert_eq!(analogy(&[], &input), None);
+    }
+}
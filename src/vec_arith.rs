@@ -0,0 +1,296 @@
+// dot products and saxpy over rows of the matrices, picking the widest
+// instructions the cpu has when first called. they work on raw pointers
+// because hogwild threads update the same rows concurrently
+use std::sync::OnceLock;
+
+type DotFn = unsafe fn(*const f32, *const f32, usize) -> f32;
+type SaxpyFn = unsafe fn(*mut f32, *const f32, f32, usize);
+
+static DOT: OnceLock<DotFn> = OnceLock::new();
+static SAXPY: OnceLock<SaxpyFn> = OnceLock::new();
+
+// sum of a[i] * b[i] for i < size
+#[inline]
+pub unsafe fn dot_product(a: *const f32, b: *const f32, size: usize) -> f32 {
+    let f = DOT.get_or_init(|| {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx512f") {
+                return x86::dot_avx512 as DotFn;
+            }
+            if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma") {
+                return x86::dot_avx2 as DotFn;
+            }
+            if is_x86_feature_detected!("avx") {
+                return x86::dot_avx as DotFn;
+            }
+        }
+        dot_scalar as DotFn
+    });
+    f(a, b, size)
+}
+
+// dst[i] += scale * source[i] for i < size
+#[inline]
+pub unsafe fn saxpy(dst: *mut f32, source: *const f32, scale: f32, size: usize) {
+    let f = SAXPY.get_or_init(|| {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx512f") {
+                return x86::saxpy_avx512 as SaxpyFn;
+            }
+            if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma") {
+                return x86::saxpy_avx2 as SaxpyFn;
+            }
+            if is_x86_feature_detected!("avx") {
+                return x86::saxpy_avx as SaxpyFn;
+            }
+        }
+        saxpy_scalar as SaxpyFn
+    });
+    f(dst, source, scale, size)
+}
+
+// four independent sums, which the compiler turns into sse on any x86_64
+unsafe fn dot_scalar(a: *const f32, b: *const f32, size: usize) -> f32 {
+    let mut acc = [0f32; 4];
+    let mut i = 0;
+    while i + 4 <= size {
+        for (j, acc) in acc.iter_mut().enumerate() {
+            *acc += *a.add(i + j) * *b.add(i + j);
+        }
+        i += 4;
+    }
+    let mut res = (acc[0] + acc[1]) + (acc[2] + acc[3]);
+    while i < size {
+        res += *a.add(i) * *b.add(i);
+        i += 1;
+    }
+    res
+}
+
+unsafe fn saxpy_scalar(dst: *mut f32, source: *const f32, scale: f32, size: usize) {
+    for i in 0..size {
+        *dst.add(i) += scale * *source.add(i);
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "avx")]
+    unsafe fn hsum256(v: __m256) -> f32 {
+        let v = _mm_add_ps(_mm256_castps256_ps128(v), _mm256_extractf128_ps(v, 1));
+        let v = _mm_add_ps(v, _mm_movehl_ps(v, v));
+        let v = _mm_add_ss(v, _mm_shuffle_ps(v, v, 1));
+        _mm_cvtss_f32(v)
+    }
+
+    #[target_feature(enable = "avx512f")]
+    pub unsafe fn dot_avx512(a: *const f32, b: *const f32, size: usize) -> f32 {
+        let (mut acc0, mut acc1) = (_mm512_setzero_ps(), _mm512_setzero_ps());
+        let mut i = 0;
+        while i + 32 <= size {
+            acc0 = _mm512_fmadd_ps(_mm512_loadu_ps(a.add(i)), _mm512_loadu_ps(b.add(i)), acc0);
+            acc1 = _mm512_fmadd_ps(
+                _mm512_loadu_ps(a.add(i + 16)),
+                _mm512_loadu_ps(b.add(i + 16)),
+                acc1,
+            );
+            i += 32;
+        }
+        if i + 16 <= size {
+            acc0 = _mm512_fmadd_ps(_mm512_loadu_ps(a.add(i)), _mm512_loadu_ps(b.add(i)), acc0);
+            i += 16;
+        }
+        if i < size {
+            let mask = (1u16 << (size - i)) - 1;
+            acc1 = _mm512_fmadd_ps(
+                _mm512_maskz_loadu_ps(mask, a.add(i)),
+                _mm512_maskz_loadu_ps(mask, b.add(i)),
+                acc1,
+            );
+        }
+        _mm512_reduce_add_ps(_mm512_add_ps(acc0, acc1))
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub unsafe fn dot_avx2(a: *const f32, b: *const f32, size: usize) -> f32 {
+        let (mut acc0, mut acc1) = (_mm256_setzero_ps(), _mm256_setzero_ps());
+        let mut i = 0;
+        while i + 16 <= size {
+            acc0 = _mm256_fmadd_ps(_mm256_loadu_ps(a.add(i)), _mm256_loadu_ps(b.add(i)), acc0);
+            acc1 = _mm256_fmadd_ps(
+                _mm256_loadu_ps(a.add(i + 8)),
+                _mm256_loadu_ps(b.add(i + 8)),
+                acc1,
+            );
+            i += 16;
+        }
+        if i + 8 <= size {
+            acc0 = _mm256_fmadd_ps(_mm256_loadu_ps(a.add(i)), _mm256_loadu_ps(b.add(i)), acc0);
+            i += 8;
+        }
+        let mut res = hsum256(_mm256_add_ps(acc0, acc1));
+        while i < size {
+            res += *a.add(i) * *b.add(i);
+            i += 1;
+        }
+        res
+    }
+
+    #[target_feature(enable = "avx")]
+    pub unsafe fn dot_avx(a: *const f32, b: *const f32, size: usize) -> f32 {
+        let (mut acc0, mut acc1) = (_mm256_setzero_ps(), _mm256_setzero_ps());
+        let mut i = 0;
+        while i + 16 <= size {
+            let p0 = _mm256_mul_ps(_mm256_loadu_ps(a.add(i)), _mm256_loadu_ps(b.add(i)));
+            let p1 = _mm256_mul_ps(_mm256_loadu_ps(a.add(i + 8)), _mm256_loadu_ps(b.add(i + 8)));
+            acc0 = _mm256_add_ps(acc0, p0);
+            acc1 = _mm256_add_ps(acc1, p1);
+            i += 16;
+        }
+        if i + 8 <= size {
+            let p0 = _mm256_mul_ps(_mm256_loadu_ps(a.add(i)), _mm256_loadu_ps(b.add(i)));
+            acc0 = _mm256_add_ps(acc0, p0);
+            i += 8;
+        }
+        let mut res = hsum256(_mm256_add_ps(acc0, acc1));
+        while i < size {
+            res += *a.add(i) * *b.add(i);
+            i += 1;
+        }
+        res
+    }
+
+    #[target_feature(enable = "avx512f")]
+    pub unsafe fn saxpy_avx512(dst: *mut f32, source: *const f32, scale: f32, size: usize) {
+        let s = _mm512_set1_ps(scale);
+        let mut i = 0;
+        while i + 16 <= size {
+            let d = _mm512_fmadd_ps(s, _mm512_loadu_ps(source.add(i)), _mm512_loadu_ps(dst.add(i)));
+            _mm512_storeu_ps(dst.add(i), d);
+            i += 16;
+        }
+        if i < size {
+            let mask = (1u16 << (size - i)) - 1;
+            let d = _mm512_fmadd_ps(
+                s,
+                _mm512_maskz_loadu_ps(mask, source.add(i)),
+                _mm512_maskz_loadu_ps(mask, dst.add(i)),
+            );
+            _mm512_mask_storeu_ps(dst.add(i), mask, d);
+        }
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub unsafe fn saxpy_avx2(dst: *mut f32, source: *const f32, scale: f32, size: usize) {
+        let s = _mm256_set1_ps(scale);
+        let mut i = 0;
+        while i + 8 <= size {
+            let d = _mm256_fmadd_ps(s, _mm256_loadu_ps(source.add(i)), _mm256_loadu_ps(dst.add(i)));
+            _mm256_storeu_ps(dst.add(i), d);
+            i += 8;
+        }
+        while i < size {
+            *dst.add(i) += scale * *source.add(i);
+            i += 1;
+        }
+    }
+
+    #[target_feature(enable = "avx")]
+    pub unsafe fn saxpy_avx(dst: *mut f32, source: *const f32, scale: f32, size: usize) {
+        let s = _mm256_set1_ps(scale);
+        let mut i = 0;
+        while i + 8 <= size {
+            let p = _mm256_mul_ps(s, _mm256_loadu_ps(source.add(i)));
+            _mm256_storeu_ps(dst.add(i), _mm256_add_ps(_mm256_loadu_ps(dst.add(i)), p));
+            i += 8;
+        }
+        while i < size {
+            *dst.add(i) += scale * *source.add(i);
+            i += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::seeded_rng;
+    use rand::Rng;
+
+    // the lengths cover empty rows and the tails after the widest vectors
+    const LENGTHS: [usize; 10] = [0, 1, 7, 8, 15, 16, 17, 32, 33, 100];
+
+    // the kernels of the cpu running the tests, by name
+    fn kernels() -> Vec<(&'static str, DotFn, SaxpyFn)> {
+        let mut kernels = vec![("scalar", dot_scalar as DotFn, saxpy_scalar as SaxpyFn)];
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx512f") {
+                kernels.push(("avx512", x86::dot_avx512, x86::saxpy_avx512));
+            }
+            if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma") {
+                kernels.push(("avx2", x86::dot_avx2, x86::saxpy_avx2));
+            }
+            if is_x86_feature_detected!("avx") {
+                kernels.push(("avx", x86::dot_avx, x86::saxpy_avx));
+            }
+        }
+        kernels
+    }
+
+    fn values(n: usize, seed: u64) -> Vec<f32> {
+        let mut rng = seeded_rng(seed, 0);
+        (0..n).map(|_| rng.gen_range(-1f32, 1.)).collect()
+    }
+
+    fn exact_dot(a: &[f32], b: &[f32]) -> f64 {
+        a.iter().zip(b).map(|(&x, &y)| x as f64 * y as f64).sum()
+    }
+
+    #[test]
+    fn dot_products_agree() {
+        for (name, dot, _) in kernels() {
+            for n in LENGTHS {
+                // one past the start, so the rows are not aligned
+                let (a, b) = (values(n + 1, 1), values(n + 1, 2));
+                let (a, b) = (&a[1..], &b[1..]);
+                let result = unsafe { dot(a.as_ptr(), b.as_ptr(), n) };
+                let scalar = unsafe { dot_scalar(a.as_ptr(), b.as_ptr(), n) };
+                let tolerance = 1e-5 * (n as f32 + 1.);
+                let error = (result - scalar).abs();
+                assert!(error < tolerance, "{} of {} values: {}", name, n, error);
+                assert!((scalar as f64 - exact_dot(a, b)).abs() < tolerance as f64);
+            }
+        }
+    }
+
+    #[test]
+    fn saxpy_agrees() {
+        for (name, _, saxpy) in kernels() {
+            for n in LENGTHS {
+                let source = values(n, 3);
+                // the value after the row must stay as it is
+                let mut dst = values(n + 1, 4);
+                let mut expected = dst.clone();
+                unsafe { saxpy_scalar(expected.as_mut_ptr(), source.as_ptr(), 0.5, n) };
+                let after = dst[n];
+                unsafe { saxpy(dst.as_mut_ptr(), source.as_ptr(), 0.5, n) };
+                for (i, (&d, &e)) in dst.iter().zip(&expected).enumerate() {
+                    assert!((d - e).abs() < 1e-6, "{} of {} values at {}", name, n, i);
+                }
+                assert_eq!(dst[n], after, "{} of {} values", name, n);
+            }
+        }
+    }
+
+    #[test]
+    fn dispatch_picks_a_kernel() {
+        let (a, b) = (values(33, 5), values(33, 6));
+        let result = unsafe { dot_product(a.as_ptr(), b.as_ptr(), 33) };
+        assert!((result as f64 - exact_dot(&a, &b)).abs() < 1e-4);
+    }
+}
@@ -1,13 +1,23 @@
 use crate::{Matrix, Dict};
+use crate::dictionary::{escape_word, unescape_word, Oov};
+use std::borrow::Cow;
 use std::io::prelude::*;
-use std::fs::File;
+use std::io::{BufReader, ErrorKind};
+use std::fs::{self, File};
+use std::path::Path;
 
+use crate::tokenizer::Tokenizer;
 use crate::utils;
+use crate::dot_product;
+use crate::hnsw::{self, Hnsw, HnswParams, IndexReport};
 pub struct Word2vec {
     syn0: Matrix,
     syn1neg: Matrix,
     dim: usize,
     dict: Dict,
+    oov: Oov,
+    // approximate nearest neighbours, exact search without it
+    index: Option<Hnsw>,
 }
 
 impl Word2vec {
@@ -17,39 +27,247 @@ impl Word2vec {
             syn1neg: syn1neg,
             dim: dim,
             dict: dict,
+            oov: Oov::Error,
+            index: None,
         }
     }
+    // how queries treat words out of the vocabulary, Oov::Unk needs a model
+    // trained with it
+    pub fn set_oov(&mut self, oov: Oov) {
+        self.oov = oov;
+    }
+    pub fn oov(&self) -> Oov {
+        self.oov
+    }
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+    pub fn vocab_size(&self) -> usize {
+        self.dict.nsize()
+    }
+    // words are tokenized like the training corpus, a model trained
+    // lowercased finds Paris as paris
+    pub fn get_index(&self, word: &str) -> Option<usize> {
+        self.dict.query_idx(word)
+    }
+    pub fn get_word(&self, index: usize) -> Option<&str> {
+        self.dict.get_word(index)
+    }
+    // the vector of a word in the vocabulary
+    pub fn get_vector(&self, word: &str) -> Option<&[f32]> {
+        self.get_index(word).map(|i| self.syn0.row(i))
+    }
+    // the vector of `word` with out of vocabulary words handled by the oov
+    // policy, None when they are skipped
+    pub fn lookup(&self, word: &str) -> Result<Option<Cow<'_, [f32]>>, utils::W2vError> {
+        if let Some(vector) = self.get_vector(word) {
+            return Ok(Some(Cow::Borrowed(vector)));
+        }
+        match self.oov {
+            Oov::Error => Err(utils::W2vError::OutOfVocabulary(word.to_string())),
+            Oov::Skip => Ok(None),
+            Oov::Zero => Ok(Some(Cow::Owned(vec![0.; self.dim]))),
+            Oov::Unk => match self.dict.unk() {
+                Some(unk) => Ok(Some(Cow::Borrowed(self.syn0.row(unk)))),
+                None => Err(utils::W2vError::OutOfVocabulary(word.to_string())),
+            },
+        }
+    }
+    // cosine similarity of two words, None when one of them is skipped and
+    // 0 for a vector of zeros
+    pub fn similarity(&self, a: &str, b: &str) -> Result<Option<f32>, utils::W2vError> {
+        let (a, b) = match (self.lookup(a)?, self.lookup(b)?) {
+            (Some(a), Some(b)) => (a, b),
+            _ => return Ok(None),
+        };
+        let dot = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum::<f32>();
+        let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+        let norms = norm(&a) * norm(&b);
+        Ok(Some(if norms > 0. { dot / norms } else { 0. }))
+    }
+    // the tokenizer the model was trained with, to split queries the same way
+    pub fn tokenizer(&self) -> &Tokenizer {
+        self.dict.tokenizer()
+    }
+    pub fn tokenize(&self, text: &str) -> Vec<String> {
+        self.dict.tokenizer().tokens(text)
+    }
     pub fn norm_self(&mut self) {
         self.syn0.norm_self();
     }
-    #[cfg(feature="blas")]
-    #[inline(always)]
-    pub fn most_similar(&self, word: &str, topn: Option<usize>) -> Vec<(f32, String)> {
-        let mut vec = vec![0.;self.dict.nsize()];
-        let c = self.dict.get_idx(word);
-        let row = self.syn0.get_row_unmod(c);
-        self.syn0.sgemv(row, vec.as_mut_ptr());
+    // the `topn`(10) words closest to `word` by cosine similarity, the word
+    // itself left out
+    pub fn most_similar(&self, word: &str, topn: Option<usize>) -> Result<Vec<(f32, String)>, utils::W2vError> {
+        match self.lookup(word)? {
+            Some(query) => self.nearest(&query, &[word], topn.unwrap_or(10)),
+            None => Ok(Vec::new()),
+        }
+    }
+    // the `topn`(10) words closest to b - a + c of the unit vectors, like
+    // man is to king as woman is to queen, the three words left out
+    pub fn analogy(&self, a: &str, b: &str, c: &str, topn: Option<usize>) -> Result<Vec<(f32, String)>, utils::W2vError> {
+        let (va, vb, vc) = match (self.lookup(a)?, self.lookup(b)?, self.lookup(c)?) {
+            (Some(va), Some(vb), Some(vc)) => (va, vb, vc),
+            _ => return Ok(Vec::new()),
+        };
+        let unit = |v: &[f32]| {
+            let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+            if norm > 0. { 1. / norm } else { 0. }
+        };
+        let (ua, ub, uc) = (unit(&va), unit(&vb), unit(&vc));
+        let query: Vec<f32> = (0..self.dim)
+            .map(|i| vb[i] * ub - va[i] * ua + vc[i] * uc)
+            .collect();
+        self.nearest(&query, &[a, b, c], topn.unwrap_or(10))
+    }
+    // the `topn` words closest to `query` by cosine similarity, the closest
+    // first, but the words of `exclude`
+    pub fn nearest(&self, query: &[f32], exclude: &[&str], topn: usize) -> Result<Vec<(f32, String)>, utils::W2vError> {
+        if query.len() != self.dim {
+            return Err(utils::W2vError::InvalidInput(format!(
+                "the query has {} values, the vectors of the model {}",
+                query.len(),
+                self.dim
+            )));
+        }
+        // no more than all words, topn comes from requests as is
+        let topn = topn.min(self.dict.nsize());
+        if let Some(ref index) = self.index {
+            let excluded: Vec<usize> = exclude.iter().filter_map(|w| self.get_index(w)).collect();
+            return Ok(index
+                .search(&self.syn0, query, topn + excluded.len())
+                .into_iter()
+                .filter(|(_, i)| !excluded.contains(i))
+                .take(topn)
+                .map(|(score, i)| (score, self.dict.idx2word[i].clone()))
+                .collect());
+        }
+        let norm = |v: &[f32]| unsafe { dot_product(v.as_ptr(), v.as_ptr(), v.len()) }.sqrt();
+        let query_norm = norm(query);
+        let excluded: Vec<usize> = exclude.iter().filter_map(|w| self.get_index(w)).collect();
         let mut sorted = Vec::new();
-        for i in 0..vec.len() {
-            sorted.push((vec[i], self.dict.get_word(i)));
+        for i in (0..self.dict.nsize()).filter(|i| !excluded.contains(i)) {
+            let row = self.syn0.row(i);
+            let norms = query_norm * norm(row);
+            let dot = unsafe { dot_product(query.as_ptr(), row.as_ptr(), self.dim) };
+            sorted.push((if norms > 0. { dot / norms } else { 0. }, i));
+        }
+        sorted.sort_by(|a, b| b.0.total_cmp(&a.0));
+        Ok(sorted
+            .into_iter()
+            .take(topn)
+            .map(|(score, i)| (score, self.dict.idx2word[i].clone()))
+            .collect())
+    }
+    // builds the approximate nearest neighbour index the queries use from
+    // now on, and measures its recall against exact search
+    pub fn build_index(&mut self, params: HnswParams, threads: u32) -> Result<IndexReport, utils::W2vError> {
+        let (index, report) = hnsw::build_checked(&self.syn0, params, threads)?;
+        self.index = Some(index);
+        Ok(report)
+    }
+    pub fn has_index(&self) -> bool {
+        self.index.is_some()
+    }
+    // candidates the index considers per query, more find more of the
+    // nearest neighbours but take longer
+    pub fn set_ef_search(&mut self, ef_search: usize) {
+        if let Some(ref mut index) = self.index {
+            index.set_ef_search(ef_search);
+        }
+    }
+    // writes the index next to the model saved as `filename`, removing the
+    // index of an earlier model there when this one has none
+    pub fn save_index(&self, filename: &str) -> Result<(), utils::W2vError> {
+        let path = filename.to_owned() + ".hnsw";
+        match self.index {
+            Some(ref index) => index.save(&path),
+            None => match fs::remove_file(&path) {
+                Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+                _ => Ok(()),
+            },
         }
-        sorted.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
-        let topn = topn.unwrap_or(10);
-        sorted.into_iter().take(topn).collect()
+    }
+    // the input vectors of all words, one row per index
+    pub fn vectors(&self) -> &[f32] {
+        self.syn0.as_slice()
+    }
+    // reads the files written by `save_vectors`, with the index when there is
+    // one. the output vectors are not saved, the model answers queries but is
+    // not trained further
+    pub fn load(filename: &str) -> Result<Word2vec, utils::W2vError> {
+        let mut model = Word2vec::load_vectors(filename)?;
+        let index = filename.to_owned() + ".hnsw";
+        if Path::new(&index).exists() {
+            model.index = Some(Hnsw::load(&index, &model.syn0)?);
+        }
+        Ok(model)
+    }
+    // `load` but for the index, to build it again
+    pub fn load_vectors(filename: &str) -> Result<Word2vec, utils::W2vError> {
+        let invalid = |reason: &str| {
+            utils::W2vError::InvalidInput(format!("{} is not a saved model: {}", filename, reason))
+        };
+        let mut lines = BufReader::new(File::open(filename)?).lines();
+        let header = lines.next().transpose()?.unwrap_or_default();
+        let (size, dim) = match header.split_once(' ') {
+            Some((size, dim)) => (
+                size.parse::<usize>().map_err(|_| invalid(&header))?,
+                dim.parse::<usize>().map_err(|_| invalid(&header))?,
+            ),
+            None => return Err(invalid(&header)),
+        };
+        if dim == 0 {
+            return Err(invalid(&header));
+        }
+        let mut words = Vec::with_capacity(size);
+        for line in lines.take(size) {
+            let line = line?;
+            match line.rsplit_once(' ').map(|(w, c)| (w, c.parse::<u32>())) {
+                // escaped like in the vocabulary file, models saved before
+                // that may have backslashes that are no escape
+                Some((word, Ok(count))) => {
+                    words.push((unescape_word(word).unwrap_or_else(|| word.to_string()), count))
+                }
+                _ => return Err(invalid(&line)),
+            }
+        }
+        if words.len() != size {
+            return Err(invalid("missing words"));
+        }
+        let bytes = fs::read(filename.to_owned() + ".vec")?;
+        if bytes.len() != size * dim * 4 {
+            return Err(invalid("the vectors do not match the vocabulary"));
+        }
+        let values = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        // models saved before the tokenizer was written split like the default
+        let tokenizer_file = filename.to_owned() + ".tokenizer";
+        let tokenizer = if Path::new(&tokenizer_file).exists() {
+            Tokenizer::load(&tokenizer_file)?
+        } else {
+            Tokenizer::default()
+        };
+        Ok(Word2vec::new(
+            Matrix::from_vec(values, dim),
+            Matrix::new(0, dim),
+            dim,
+            Dict::from_saved(words, tokenizer),
+        ))
     }
     pub fn save_vectors(&self, filename: &str) -> Result<bool, utils::W2vError> {
         let size = self.dict.nsize();
         let mut file = File::create(filename)?;
         let mut meta = Vec::new();
 
-        write!(&mut meta, "{} {}\n", size, self.dim)?;
+        writeln!(&mut meta, "{} {}", size, self.dim)?;
         file.write_all(&meta)?;
-        for i in 0..size {
-          let word = self.dict.get_word(i);
-            let freq = self.dict.get_entry(&word);
-            file.write(&word.into_bytes()[..])?;
-            let s = format!(" {}\n",freq.count);
-            file.write(&s.into_bytes()[..])?;
+        for (word, count) in self.dict.idx2word.iter().zip(self.dict.counts()) {
+            file.write_all(escape_word(word).as_bytes())?;
+            let s = format!(" {}\n", count);
+            file.write_all(s.as_bytes())?;
         }
         use std::mem;
         use std::slice;
@@ -59,9 +277,107 @@ impl Word2vec {
             let ptr = mem::transmute::<*const f32,*const u8>(ptr);
             let u8data = slice::from_raw_parts(ptr,
                                                size*self.dim*4);
-            file.write(u8data)?;
+            file.write_all(u8data)?;
         };
+        self.dict.tokenizer().save(&(filename.to_owned() + ".tokenizer"))?;
+        self.save_index(filename)?;
         Ok(true)
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dictionary::UNK_TOKEN;
 
+    // words along the axes of the plane and one between x and y
+    fn model() -> Word2vec {
+        let words = ["x", "y", "-x", "-y", "xy"];
+        let vectors = vec![1., 0., 0., 1., -1., 0., 0., -1., 1., 1.];
+        let counts = words.iter().map(|w| (w.to_string(), 1)).collect();
+        let dict = Dict::from_saved(counts, Tokenizer::default());
+        Word2vec::new(Matrix::from_vec(vectors, 2), Matrix::new(0, 2), 2, dict)
+    }
+
+    #[test]
+    fn most_similar_takes_any_topn() {
+        let mut model = model();
+        for indexed in [false, true] {
+            if indexed {
+                model.build_index(HnswParams::default(), 1).unwrap();
+            }
+            let similar = model.most_similar("x", Some(usize::MAX)).unwrap();
+            let words: Vec<&str> = similar.iter().map(|(_, w)| w.as_str()).collect();
+            assert_eq!(words, ["xy", "y", "-y", "-x"]);
+            assert!(model.most_similar("x", Some(0)).unwrap().is_empty());
+        }
+    }
+    #[test]
+    fn queries_are_tokenized_like_the_corpus() {
+        let tokenizer = Tokenizer {
+            lowercase: true,
+            nfkc: true,
+            ..Tokenizer::default()
+        };
+        let counts = vec![("paris".to_string(), 2), ("rome".to_string(), 1)];
+        let dict = Dict::from_saved(counts, tokenizer);
+        let vectors = Matrix::from_vec(vec![1., 0., 0., 1.], 2);
+        let model = Word2vec::new(vectors, Matrix::new(0, 2), 2, dict);
+        assert_eq!(model.get_index("Paris"), Some(0));
+        assert_eq!(model.get_vector("ＲＯＭＥ"), Some(&[0., 1.][..]));
+        let similar = model.most_similar("PARIS", None).unwrap();
+        assert_eq!(similar, [(0., "rome".to_string())]);
+    }
+    #[test]
+    fn words_with_separators_are_saved() {
+        let words = ["a b", "c\nd", "e\\f", "g\th", "\\n"];
+        let counts = words.iter().map(|w| (w.to_string(), 1)).collect();
+        let dict = Dict::from_saved(counts, Tokenizer::default());
+        let vectors = Matrix::from_vec((0..10).map(|x| x as f32).collect(), 2);
+        let model = Word2vec::new(vectors, Matrix::new(0, 2), 2, dict);
+        let path = std::env::temp_dir().join(format!("escaped-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        model.save_vectors(path).unwrap();
+        let loaded = Word2vec::load(path);
+        for suffix in ["", ".vec", ".tokenizer"] {
+            fs::remove_file(format!("{}{}", path, suffix)).unwrap();
+        }
+        let loaded = loaded.unwrap();
+        for (i, word) in words.iter().enumerate() {
+            assert_eq!(loaded.get_word(i), Some(*word));
+            assert_eq!(loaded.get_vector(word), model.get_vector(word));
+        }
+    }
+    #[test]
+    fn out_of_vocabulary_policies() {
+        let mut model = model();
+        assert_eq!(model.oov(), Oov::Error);
+        match model.lookup("z") {
+            Err(utils::W2vError::OutOfVocabulary(word)) => assert_eq!(word, "z"),
+            other => panic!("{:?}", other),
+        }
+        assert!(model.most_similar("z", None).is_err());
+
+        model.set_oov(Oov::Skip);
+        assert_eq!(model.lookup("z").unwrap(), None);
+        assert_eq!(model.similarity("x", "z").unwrap(), None);
+        assert!(model.most_similar("z", None).unwrap().is_empty());
+        assert!(model.analogy("x", "y", "z", None).unwrap().is_empty());
+
+        model.set_oov(Oov::Zero);
+        assert_eq!(model.lookup("z").unwrap().as_deref(), Some(&[0., 0.][..]));
+        assert_eq!(model.similarity("x", "z").unwrap(), Some(0.));
+
+        // a model trained without <unk> has no vector for it
+        model.set_oov(Oov::Unk);
+        assert!(model.lookup("z").is_err());
+        let counts = vec![("x".to_string(), 1), (UNK_TOKEN.to_string(), 1)];
+        let dict = Dict::from_saved(counts, Tokenizer::default());
+        let vectors = Matrix::from_vec(vec![1., 0., 0., 1.], 2);
+        let mut model = Word2vec::new(vectors, Matrix::new(0, 2), 2, dict);
+        model.set_oov(Oov::Unk);
+        assert_eq!(model.lookup("z").unwrap().as_deref(), Some(&[0., 1.][..]));
+        assert_eq!(model.lookup("x").unwrap().as_deref(), Some(&[1., 0.][..]));
+        assert_eq!(model.similarity("z", "zz").unwrap(), Some(1.));
+    }
 }